@@ -0,0 +1,108 @@
+use std::time::Duration;
+
+use cgmath::{InnerSpace, Rotation3};
+
+struct Drag {
+    center: cgmath::Vector2<f32>,
+    radius: f32,
+    last: cgmath::Vector3<f32>,
+}
+
+// Maps cursor drags onto a virtual sphere around an object, producing
+// rotations in view space (x right, y up, z towards the viewer). After the
+// drag ends the last angular velocity carries on and decays at `damping`
+pub struct Arcball {
+    damping: f32,
+    drag: Option<Drag>,
+    pending: cgmath::Quaternion<f32>,
+    // rotation axis scaled by radians per second
+    angular_velocity: cgmath::Vector3<f32>,
+}
+
+impl Arcball {
+    pub fn new(damping: f32) -> Self {
+        Self {
+            damping,
+            drag: None,
+            pending: cgmath::Quaternion::new(1.0, 0.0, 0.0, 0.0),
+            angular_velocity: cgmath::Vector3::new(0.0, 0.0, 0.0),
+        }
+    }
+
+    pub fn is_dragging(&self) -> bool {
+        self.drag.is_some()
+    }
+
+    // `center` and `radius` describe the sphere on screen, in pixels
+    pub fn begin(&mut self, cursor: cgmath::Vector2<f32>, center: cgmath::Vector2<f32>, radius: f32) {
+        let radius = radius.max(1.0);
+        self.stop();
+        self.drag = Some(Drag {
+            center,
+            radius,
+            last: sphere_point(cursor, center, radius),
+        });
+    }
+
+    pub fn drag(&mut self, cursor: cgmath::Vector2<f32>) {
+        if let Some(drag) = &mut self.drag {
+            let current = sphere_point(cursor, drag.center, drag.radius);
+            let axis = drag.last.cross(current);
+            if axis.magnitude2() > f32::EPSILON * f32::EPSILON {
+                let angle = drag.last.dot(current).clamp(-1.0, 1.0).acos();
+                self.pending = cgmath::Quaternion::from_axis_angle(axis.normalize(), cgmath::Rad(angle)) * self.pending;
+            }
+            drag.last = current;
+        }
+    }
+
+    pub fn end(&mut self) {
+        self.drag = None;
+    }
+
+    pub fn stop(&mut self) {
+        self.pending = cgmath::Quaternion::new(1.0, 0.0, 0.0, 0.0);
+        self.angular_velocity = cgmath::Vector3::new(0.0, 0.0, 0.0);
+    }
+
+    // View space rotation to apply this frame, if any
+    pub fn update(&mut self, dt: Duration) -> Option<cgmath::Quaternion<f32>> {
+        let dt = dt.as_secs_f32();
+        if self.drag.is_some() {
+            let rotation = std::mem::replace(&mut self.pending, cgmath::Quaternion::new(1.0, 0.0, 0.0, 0.0));
+            let (axis, angle) = axis_angle(rotation);
+            if dt > 0.0 {
+                self.angular_velocity = axis * (angle / dt);
+            }
+            return (angle > 0.0).then_some(rotation);
+        }
+
+        let speed = self.angular_velocity.magnitude();
+        if speed < 1e-3 {
+            self.angular_velocity = cgmath::Vector3::new(0.0, 0.0, 0.0);
+            return None;
+        }
+        let rotation = cgmath::Quaternion::from_axis_angle(self.angular_velocity / speed, cgmath::Rad(speed * dt));
+        self.angular_velocity *= (-self.damping * dt).exp();
+        Some(rotation)
+    }
+}
+
+// Shoemake's sphere with Holroyd's hyperbolic sheet outside it, so dragging
+// past the edge keeps rotating smoothly instead of snapping to the rim
+fn sphere_point(cursor: cgmath::Vector2<f32>, center: cgmath::Vector2<f32>, radius: f32) -> cgmath::Vector3<f32> {
+    let x = (cursor.x - center.x) / radius;
+    let y = (center.y - cursor.y) / radius;
+    let d2 = x * x + y * y;
+    let z = if d2 <= 0.5 { (1.0 - d2).sqrt() } else { 0.5 / d2.sqrt() };
+    cgmath::Vector3::new(x, y, z).normalize()
+}
+
+fn axis_angle(q: cgmath::Quaternion<f32>) -> (cgmath::Vector3<f32>, f32) {
+    let q = if q.s < 0.0 { -q } else { q };
+    let sin_half = q.v.magnitude();
+    if sin_half < f32::EPSILON {
+        return (cgmath::Vector3::unit_z(), 0.0);
+    }
+    (q.v / sin_half, 2.0 * sin_half.atan2(q.s))
+}
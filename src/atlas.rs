@@ -0,0 +1,495 @@
+use anyhow::*;
+
+use crate::{
+    mipmap,
+    sampler::SamplerCache,
+    texture::{Texture, TextureOptions},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct AtlasId(usize);
+
+// Texture coordinates of one image inside the atlas
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct UvRect {
+    pub min: [f32; 2],
+    pub max: [f32; 2],
+}
+
+impl UvRect {
+    // Maps coordinates in 0..1 across the original image into the atlas, so
+    // existing `tex_coords` can be reused as they are
+    pub fn map(&self, uv: [f32; 2]) -> [f32; 2] {
+        [
+            self.min[0] + uv[0] * (self.max[0] - self.min[0]),
+            self.min[1] + uv[1] * (self.max[1] - self.min[1]),
+        ]
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Placement {
+    x: u32,
+    y: u32,
+}
+
+struct Entry {
+    image: image::RgbaImage,
+    placement: Option<Placement>,
+}
+
+// Packs many small images into one texture. Each image is surrounded by
+// `padding` pixels copied from its own edges, so filtering, and the first
+// few mip levels, never pick up a neighbour. Placements are aligned so that
+// no texel of those mip levels straddles two images. The images are kept on
+// the CPU so the atlas can grow and be repacked
+pub struct TextureAtlas {
+    padding: u32,
+    max_size: u32,
+    packer: SkylinePacker,
+    entries: Vec<Option<Entry>>,
+    texture: Option<Texture>,
+    dirty: bool,
+}
+
+impl TextureAtlas {
+    pub fn new(initial_size: u32, max_size: u32, padding: u32) -> Self {
+        let size = initial_size.min(max_size);
+        Self {
+            padding,
+            max_size,
+            packer: SkylinePacker::new(size, size),
+            entries: Vec::new(),
+            texture: None,
+            dirty: true,
+        }
+    }
+
+    pub fn size(&self) -> (u32, u32) {
+        (self.packer.width, self.packer.height)
+    }
+
+    // Adds `image`, growing the atlas when it doesn't fit. Fails only once the
+    // atlas would exceed `max_size`
+    pub fn insert(&mut self, image: image::RgbaImage) -> Result<AtlasId> {
+        let (width, height) = self.padded_size(&image)?;
+        let id = AtlasId(self.entries.len());
+        let placement = self.packer.pack(width, height);
+        self.entries.push(Some(Entry { image, placement }));
+        self.dirty = true;
+
+        if placement.is_none() {
+            while !self.repack() {
+                if !self.grow() {
+                    self.entries.pop();
+                    self.repack();
+                    bail!("Texture atlas is full at {}x{}", self.packer.width, self.packer.height);
+                }
+            }
+        }
+        Ok(id)
+    }
+
+    // Swaps the image of `id` for another, which can be a different size.
+    // Everything is repacked, so other images may move too
+    pub fn replace(&mut self, id: AtlasId, image: image::RgbaImage) -> Result<()> {
+        self.padded_size(&image)?;
+        let Some(Some(entry)) = self.entries.get_mut(id.0) else {
+            bail!("{:?} isn't in the texture atlas", id);
+        };
+        let previous = std::mem::replace(&mut entry.image, image);
+        while !self.repack() {
+            if !self.grow() {
+                if let Some(entry) = &mut self.entries[id.0] {
+                    entry.image = previous;
+                }
+                self.repack();
+                bail!("Texture atlas is full at {}x{}", self.packer.width, self.packer.height);
+            }
+        }
+        Ok(())
+    }
+
+    fn padded_size(&self, image: &image::RgbaImage) -> Result<(u32, u32)> {
+        // There'd be no edge texel to bleed into the padding
+        if image.width() == 0 || image.height() == 0 {
+            bail!("Can't add an empty {}x{} image to a texture atlas", image.width(), image.height());
+        }
+        let (width, height) = self.footprint(image);
+        if width > self.max_size || height > self.max_size {
+            bail!("{}x{} image can't fit in an atlas of at most {} pixels", image.width(), image.height(), self.max_size);
+        }
+        Ok((width, height))
+    }
+
+    // Mip levels the padding keeps apart, counting the base level
+    fn mip_levels(&self) -> u32 {
+        mipmap::mip_level_count(self.padding.max(1), 1)
+    }
+
+    // Space taken by `image` with its padding, rounded up to a whole texel
+    // of the smallest mip level. Every footprint being a multiple of that
+    // keeps every placement on a multiple of it too
+    fn footprint(&self, image: &image::RgbaImage) -> (u32, u32) {
+        let alignment = 1 << (self.mip_levels() - 1);
+        (
+            (image.width() + 2 * self.padding).next_multiple_of(alignment),
+            (image.height() + 2 * self.padding).next_multiple_of(alignment),
+        )
+    }
+
+    // Frees the space of `id` on the next repack
+    #[allow(unused)]
+    pub fn remove(&mut self, id: AtlasId) {
+        if let Some(entry) = self.entries.get_mut(id.0) {
+            *entry = None;
+        }
+    }
+
+    // Packs every image again from scratch, tallest first, which usually
+    // leaves less wasted space than the insertion order did. Returns false
+    // if they no longer fit at the current size
+    pub fn repack(&mut self) -> bool {
+        let mut order = self.entries
+            .iter()
+            .enumerate()
+            .filter_map(|(index, entry)| entry.as_ref().map(|e| {
+                let (width, height) = self.footprint(&e.image);
+                (index, width, height)
+            }))
+            .collect::<Vec<_>>();
+        order.sort_by_key(|&(_, width, height)| std::cmp::Reverse((height, width)));
+
+        self.packer = SkylinePacker::new(self.packer.width, self.packer.height);
+        self.dirty = true;
+        let mut fits = true;
+        for (index, width, height) in order {
+            let placement = self.packer.pack(width, height);
+            fits &= placement.is_some();
+            if let Some(entry) = &mut self.entries[index] {
+                entry.placement = placement;
+            }
+        }
+        fits
+    }
+
+    // Doubles the shorter side, or returns false at `max_size`
+    fn grow(&mut self) -> bool {
+        let (width, height) = self.size();
+        let grown = if width <= height { (width * 2, height) } else { (width, height * 2) };
+        if grown.0 > self.max_size || grown.1 > self.max_size {
+            return false;
+        }
+        self.packer = SkylinePacker::new(grown.0, grown.1);
+        true
+    }
+
+    pub fn uv_rect(&self, id: AtlasId) -> Option<UvRect> {
+        let entry = self.entries.get(id.0)?.as_ref()?;
+        let placement = entry.placement?;
+        let (width, height) = self.size();
+        let x = (placement.x + self.padding) as f32;
+        let y = (placement.y + self.padding) as f32;
+        Some(UvRect {
+            min: [x / width as f32, y / height as f32],
+            max: [(x + entry.image.width() as f32) / width as f32, (y + entry.image.height() as f32) / height as f32],
+        })
+    }
+
+    // Rebuilds the texture if anything changed since the last upload and
+    // returns true when it did, in which case bind groups using it need to
+    // be recreated. Mip levels are capped so that the padding still covers
+    // the filter footprint at the smallest one
+    pub fn upload(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        options: &TextureOptions,
+        samplers: &SamplerCache,
+    ) -> Result<bool> {
+        if !self.dirty && self.texture.is_some() {
+            return Ok(false);
+        }
+
+        let options = options.with_max_mip_levels(self.mip_levels());
+        self.texture = Some(Texture::from_image(
+            device,
+            queue,
+            &image::DynamicImage::ImageRgba8(self.image()),
+            Some("texture_atlas"),
+            &options,
+            samplers,
+        )?);
+        self.dirty = false;
+        Ok(true)
+    }
+
+    pub fn texture(&self) -> Option<&Texture> {
+        self.texture.as_ref()
+    }
+
+    // The whole atlas, with each image's edges repeated across the rest of
+    // its footprint
+    fn image(&self) -> image::RgbaImage {
+        let (width, height) = self.size();
+        let mut atlas = image::RgbaImage::new(width, height);
+        let padding = self.padding;
+        for entry in self.entries.iter().flatten() {
+            let Some(placement) = entry.placement else {
+                continue;
+            };
+            let (image_width, image_height) = entry.image.dimensions();
+            let (footprint_width, footprint_height) = self.footprint(&entry.image);
+            for y in 0..footprint_height {
+                for x in 0..footprint_width {
+                    let source_x = x.saturating_sub(padding).min(image_width - 1);
+                    let source_y = y.saturating_sub(padding).min(image_height - 1);
+                    atlas.put_pixel(placement.x + x, placement.y + y, *entry.image.get_pixel(source_x, source_y));
+                }
+            }
+        }
+        atlas
+    }
+}
+
+// Skyline bottom-left packer: the top edge of everything placed so far is
+// kept as a list of horizontal segments, and each rectangle goes wherever it
+// ends up lowest, breaking ties by the narrower segment
+struct SkylinePacker {
+    width: u32,
+    height: u32,
+    skyline: Vec<Segment>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Segment {
+    x: u32,
+    y: u32,
+    width: u32,
+}
+
+impl SkylinePacker {
+    fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            skyline: vec![Segment { x: 0, y: 0, width }],
+        }
+    }
+
+    fn pack(&mut self, width: u32, height: u32) -> Option<Placement> {
+        let mut best: Option<(usize, u32, u32)> = None;
+        for index in 0..self.skyline.len() {
+            if let Some(y) = self.fits(index, width, height) {
+                let segment_width = self.skyline[index].width;
+                let better = match best {
+                    None => true,
+                    Some((_, best_y, best_width)) => y < best_y || (y == best_y && segment_width < best_width),
+                };
+                if better {
+                    best = Some((index, y, segment_width));
+                }
+            }
+        }
+
+        let (index, y, _) = best?;
+        let x = self.skyline[index].x;
+        self.place(index, x, y + height, width);
+        Some(Placement { x, y })
+    }
+
+    // Height the rectangle would sit at with its left edge on segment `index`
+    fn fits(&self, index: usize, width: u32, height: u32) -> Option<u32> {
+        let x = self.skyline[index].x;
+        if x + width > self.width {
+            return None;
+        }
+        let mut y = 0;
+        let mut remaining = width as i64;
+        for segment in &self.skyline[index..] {
+            if remaining <= 0 {
+                break;
+            }
+            y = y.max(segment.y);
+            if y + height > self.height {
+                return None;
+            }
+            remaining -= segment.width as i64;
+        }
+        Some(y)
+    }
+
+    fn place(&mut self, index: usize, x: u32, top: u32, width: u32) {
+        self.skyline.insert(index, Segment { x, y: top, width });
+
+        // Trim or drop the segments now underneath the new one
+        let right = x + width;
+        let next = index + 1;
+        while next < self.skyline.len() {
+            let segment = self.skyline[next];
+            if segment.x >= right {
+                break;
+            }
+            let overlap = right - segment.x;
+            if overlap >= segment.width {
+                self.skyline.remove(next);
+            } else {
+                self.skyline[next].x += overlap;
+                self.skyline[next].width -= overlap;
+                break;
+            }
+        }
+
+        // Merge neighbours at the same height
+        let mut i = 0;
+        while i + 1 < self.skyline.len() {
+            if self.skyline[i].y == self.skyline[i + 1].y {
+                self.skyline[i].width += self.skyline[i + 1].width;
+                self.skyline.remove(i + 1);
+            } else {
+                i += 1;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn solid(width: u32, height: u32, value: u8) -> image::RgbaImage {
+        image::RgbaImage::from_pixel(width, height, image::Rgba([value, value, value, 255]))
+    }
+
+    // Footprints of every placed image, as x, y, width, height
+    fn padded_rects(atlas: &TextureAtlas) -> Vec<(u32, u32, u32, u32)> {
+        atlas.entries
+            .iter()
+            .flatten()
+            .map(|entry| {
+                let placement = entry.placement.expect("every image should be placed");
+                let (width, height) = atlas.footprint(&entry.image);
+                (placement.x, placement.y, width, height)
+            })
+            .collect()
+    }
+
+    fn assert_disjoint(atlas: &TextureAtlas) {
+        let rects = padded_rects(atlas);
+        let (width, height) = atlas.size();
+        for (i, a) in rects.iter().enumerate() {
+            assert!(a.0 + a.2 <= width && a.1 + a.3 <= height, "{:?} is outside {}x{}", a, width, height);
+            for b in &rects[i + 1..] {
+                let apart = a.0 + a.2 <= b.0 || b.0 + b.2 <= a.0 || a.1 + a.3 <= b.1 || b.1 + b.3 <= a.1;
+                assert!(apart, "{:?} overlaps {:?}", a, b);
+            }
+        }
+    }
+
+    #[test]
+    fn entries_never_overlap() {
+        let mut atlas = TextureAtlas::new(64, 1024, 4);
+        let sizes = [(10, 10), (30, 5), (7, 40), (16, 16), (50, 12), (3, 3), (25, 25), (1, 60), (60, 1), (12, 33)];
+        for (index, (width, height)) in sizes.iter().enumerate() {
+            atlas.insert(solid(*width, *height, index as u8)).unwrap();
+            assert_disjoint(&atlas);
+        }
+        let id = atlas.insert(solid(20, 20, 100)).unwrap();
+        atlas.remove(id);
+        assert!(atlas.repack());
+        assert_disjoint(&atlas);
+    }
+
+    #[test]
+    fn growing_keeps_earlier_entries() {
+        let mut atlas = TextureAtlas::new(32, 256, 2);
+        let first = atlas.insert(solid(20, 20, 1)).unwrap();
+        let second = atlas.insert(solid(4, 4, 2)).unwrap();
+        assert_eq!(atlas.size(), (32, 32));
+
+        let large = atlas.insert(solid(100, 50, 3)).unwrap();
+        assert!(atlas.size().0 > 32 && atlas.size().1 > 32, "{:?}", atlas.size());
+        for (id, value) in [(first, 1), (second, 2), (large, 3)] {
+            assert!(atlas.uv_rect(id).is_some(), "{:?} lost its place", id);
+            let entry = atlas.entries[id.0].as_ref().unwrap();
+            assert_eq!(entry.image.get_pixel(0, 0)[0], value);
+        }
+        assert_disjoint(&atlas);
+    }
+
+    #[test]
+    fn failed_insert_and_replace_leave_the_atlas_as_it_was() {
+        // Four 28x28 images fill the whole 64x64 atlas
+        let mut atlas = TextureAtlas::new(32, 64, 2);
+        let ids: Vec<_> = (0..4).map(|value| atlas.insert(solid(28, 28, value)).unwrap()).collect();
+        assert_eq!(atlas.size(), (64, 64));
+
+        assert!(atlas.insert(solid(10, 10, 9)).is_err());
+        assert_eq!(atlas.entries.len(), 4);
+        assert!(atlas.replace(ids[1], solid(60, 60, 9)).is_err());
+        let entry = atlas.entries[ids[1].0].as_ref().unwrap();
+        assert_eq!(entry.image.dimensions(), (28, 28));
+        assert_eq!(entry.image.get_pixel(0, 0)[0], 1);
+        for id in &ids {
+            assert!(atlas.uv_rect(*id).is_some());
+        }
+        assert_disjoint(&atlas);
+
+        // A smaller image still fits in its place
+        atlas.replace(ids[1], solid(5, 5, 7)).unwrap();
+        assert_disjoint(&atlas);
+    }
+
+    #[test]
+    fn empty_and_oversized_images_are_rejected() {
+        let mut atlas = TextureAtlas::new(32, 64, 2);
+        assert!(atlas.insert(solid(0, 10, 1)).is_err());
+        assert!(atlas.insert(solid(61, 10, 1)).is_err());
+        let id = atlas.insert(solid(60, 10, 1)).unwrap();
+        assert!(atlas.replace(id, solid(10, 0, 1)).is_err());
+        assert_eq!(atlas.entries.len(), 1);
+    }
+
+    #[test]
+    fn smallest_mip_keeps_images_apart() {
+        let mut atlas = TextureAtlas::new(64, 1024, 4);
+        let sizes = [(5, 7), (13, 2), (9, 9), (1, 1), (30, 11), (6, 21)];
+        for (index, (width, height)) in sizes.iter().enumerate() {
+            atlas.insert(solid(*width, *height, 40 * index as u8 + 20)).unwrap();
+        }
+        let alignment = 1 << (atlas.mip_levels() - 1);
+        for rect in padded_rects(&atlas) {
+            assert!([rect.0, rect.1, rect.2, rect.3].iter().all(|v| v % alignment == 0), "{:?} isn't aligned to {}", rect, alignment);
+        }
+
+        // Every texel of the smallest level inside an image's footprint is
+        // made only from that image's solid colour
+        let chain = mipmap::cpu_mip_chain(&atlas.image(), true);
+        let smallest = &chain[atlas.mip_levels() as usize - 2];
+        for entry in atlas.entries.iter().flatten() {
+            let placement = entry.placement.unwrap();
+            let (width, height) = atlas.footprint(&entry.image);
+            for y in (placement.y..placement.y + height).step_by(alignment as usize) {
+                for x in (placement.x..placement.x + width).step_by(alignment as usize) {
+                    assert_eq!(smallest.get_pixel(x / alignment, y / alignment), entry.image.get_pixel(0, 0), "at {}, {}", x, y);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn uv_rect_follows_growth() {
+        let mut atlas = TextureAtlas::new(16, 256, 4);
+        let id = atlas.insert(solid(6, 4, 1)).unwrap();
+        atlas.insert(solid(40, 40, 2)).unwrap();
+        let (width, height) = atlas.size();
+        assert!(width >= 48 && height >= 48, "{}x{}", width, height);
+
+        let placement = atlas.entries[id.0].as_ref().unwrap().placement.unwrap();
+        let uv = atlas.uv_rect(id).unwrap();
+        let texel = |uv: [f32; 2]| [uv[0] * width as f32, uv[1] * height as f32];
+        assert_eq!(texel(uv.min), [(placement.x + 4) as f32, (placement.y + 4) as f32]);
+        assert_eq!(texel(uv.max), [(placement.x + 10) as f32, (placement.y + 8) as f32]);
+        assert_eq!(uv.map([0.5, 0.5]), [(uv.min[0] + uv.max[0]) / 2.0, (uv.min[1] + uv.max[1]) / 2.0]);
+    }
+}
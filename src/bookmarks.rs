@@ -0,0 +1,271 @@
+use std::path::Path;
+
+use anyhow::*;
+
+use crate::camera::Camera;
+
+// Bookmark that sets the initial view when the file is loaded on startup
+pub const START_BOOKMARK: &str = "start";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Bookmark {
+    pub name: String,
+    pub eye: cgmath::Point3<f32>,
+    pub target: cgmath::Point3<f32>,
+    pub up: cgmath::Vector3<f32>,
+    pub fovy: f32,
+    pub znear: f32,
+    pub zfar: f32,
+}
+
+impl Bookmark {
+    pub fn from_camera(name: &str, camera: &Camera) -> Self {
+        Self {
+            name: name.to_string(),
+            eye: camera.eye,
+            target: camera.target,
+            up: camera.up,
+            fovy: camera.fovy,
+            znear: camera.znear,
+            zfar: camera.zfar,
+        }
+    }
+
+    // Projection, aspect and depth mode stay as they are on `camera`
+    pub fn applied_to(&self, camera: &Camera) -> Camera {
+        Camera {
+            eye: self.eye,
+            target: self.target,
+            up: self.up,
+            fovy: self.fovy,
+            znear: self.znear,
+            zfar: self.zfar,
+            ..*camera
+        }
+    }
+}
+
+// Bookmarks keep the order they were read or added in, so saving doesn't
+// shuffle a hand-edited file
+pub struct Bookmarks {
+    bookmarks: Vec<Bookmark>,
+}
+
+impl Bookmarks {
+    pub fn new() -> Self {
+        Self {
+            bookmarks: Vec::new(),
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Bookmark> {
+        self.bookmarks.iter().find(|b| b.name == name)
+    }
+
+    pub fn set(&mut self, bookmark: Bookmark) {
+        match self.bookmarks.iter_mut().find(|b| b.name == bookmark.name) {
+            Some(existing) => *existing = bookmark,
+            None => self.bookmarks.push(bookmark),
+        }
+    }
+
+    // Sections of `key = value` lines, e.g.
+    //
+    //   [start]
+    //   eye = 0 1 2
+    //   target = 0 0 0
+    //
+    // `eye` and `target` are required; `up`, `fovy`, `znear` and `zfar`
+    // default to +Y, 45, 0.1 and 100. '#' starts a comment line
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("Couldn't read camera bookmarks {}", path.display()))?;
+
+        let mut bookmarks = Self::new();
+        let mut current: Option<PartialBookmark> = None;
+        for (number, line) in contents.lines().enumerate() {
+            let location = || format!("{}:{}", path.display(), number + 1);
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                if name.trim().is_empty() {
+                    bail!("{}: bookmark names can't be empty", location());
+                }
+                if let Some(partial) = current.take() {
+                    bookmarks.set(partial.finish()?);
+                }
+                current = Some(PartialBookmark::new(name.trim(), location()));
+                continue;
+            }
+
+            let Some(partial) = current.as_mut() else {
+                bail!("{}: expected a [name] line before any values", location());
+            };
+            let Some((key, value)) = line.split_once('=') else {
+                bail!("{}: expected `key = value`", location());
+            };
+            partial.set(key.trim(), value.trim()).with_context(location)?;
+        }
+        if let Some(partial) = current.take() {
+            bookmarks.set(partial.finish()?);
+        }
+
+        Ok(bookmarks)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        let mut contents = String::from("# Camera bookmarks, [start] sets the initial view\n");
+        for b in &self.bookmarks {
+            contents.push_str(&format!(
+                "\n[{}]\neye = {} {} {}\ntarget = {} {} {}\nup = {} {} {}\nfovy = {}\nznear = {}\nzfar = {}\n",
+                b.name,
+                b.eye.x, b.eye.y, b.eye.z,
+                b.target.x, b.target.y, b.target.z,
+                b.up.x, b.up.y, b.up.z,
+                b.fovy, b.znear, b.zfar,
+            ));
+        }
+
+        std::fs::write(path, contents)
+            .with_context(|| format!("Couldn't write camera bookmarks {}", path.display()))
+    }
+}
+
+struct PartialBookmark {
+    name: String,
+    location: String,
+    eye: Option<cgmath::Point3<f32>>,
+    target: Option<cgmath::Point3<f32>>,
+    up: cgmath::Vector3<f32>,
+    fovy: f32,
+    znear: f32,
+    zfar: f32,
+}
+
+impl PartialBookmark {
+    fn new(name: &str, location: String) -> Self {
+        Self {
+            name: name.to_string(),
+            location,
+            eye: None,
+            target: None,
+            up: cgmath::Vector3::unit_y(),
+            fovy: 45.0,
+            znear: 0.1,
+            zfar: 100.0,
+        }
+    }
+
+    fn set(&mut self, key: &str, value: &str) -> Result<()> {
+        match key {
+            "eye" => self.eye = Some(parse_vector(value)?.into()),
+            "target" => self.target = Some(parse_vector(value)?.into()),
+            "up" => self.up = parse_vector(value)?.into(),
+            "fovy" => self.fovy = parse_number(value)?,
+            "znear" => self.znear = parse_number(value)?,
+            "zfar" => self.zfar = parse_number(value)?,
+            _ => bail!("unknown key `{}`", key),
+        }
+        Ok(())
+    }
+
+    fn finish(self) -> Result<Bookmark> {
+        let (Some(eye), Some(target)) = (self.eye, self.target) else {
+            bail!("{}: bookmark [{}] needs both `eye` and `target`", self.location, self.name);
+        };
+        Ok(Bookmark {
+            name: self.name,
+            eye,
+            target,
+            up: self.up,
+            fovy: self.fovy,
+            znear: self.znear,
+            zfar: self.zfar,
+        })
+    }
+}
+
+fn parse_number(value: &str) -> Result<f32> {
+    value.parse().with_context(|| format!("invalid number `{}`", value))
+}
+
+fn parse_vector(value: &str) -> Result<[f32; 3]> {
+    let values = value.split_whitespace().map(parse_number).collect::<Result<Vec<_>>>()?;
+    values.try_into().map_err(|_| anyhow!("expected three numbers, found `{}`", value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bookmark(name: &str, eye: [f32; 3], target: [f32; 3]) -> Bookmark {
+        Bookmark {
+            name: name.to_string(),
+            eye: eye.into(),
+            target: target.into(),
+            up: cgmath::Vector3::unit_y(),
+            fovy: 45.0,
+            znear: 0.1,
+            zfar: 100.0,
+        }
+    }
+
+    // Writes `contents` to a file of its own and loads it back
+    fn load_str(name: &str, contents: &str) -> Result<Bookmarks> {
+        let path = std::env::temp_dir().join(format!("bookmarks_{}_{}.txt", name, std::process::id()));
+        std::fs::write(&path, contents).unwrap();
+        let bookmarks = Bookmarks::load(&path);
+        std::fs::remove_file(&path).unwrap();
+        bookmarks
+    }
+
+    #[test]
+    fn save_and_load_round_trip() {
+        let mut bookmarks = Bookmarks::new();
+        bookmarks.set(bookmark(START_BOOKMARK, [0.0, 1.0, 2.0], [0.0, 0.0, 0.0]));
+        bookmarks.set(Bookmark {
+            up: cgmath::Vector3::new(0.0, 0.0, 1.0),
+            fovy: 72.5,
+            znear: 0.01,
+            zfar: 1.0e4,
+            ..bookmark("3", [-1.0 / 3.0, 2.5e-7, 100.0], [4.0, -5.0, 6.125])
+        });
+        bookmarks.set(bookmark("overview", [10.0, 10.0, 10.0], [1.0, 0.0, 1.0]));
+
+        let path = std::env::temp_dir().join(format!("bookmarks_round_trip_{}.txt", std::process::id()));
+        bookmarks.save(&path).unwrap();
+        let loaded = Bookmarks::load(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.unwrap().bookmarks, bookmarks.bookmarks);
+    }
+
+    #[test]
+    fn missing_values_take_defaults() {
+        let bookmarks = load_str("defaults", "# comment\n\n[a]\neye = 1 2 3\ntarget = 0 0 0\n").unwrap();
+        assert_eq!(bookmarks.get("a"), Some(&bookmark("a", [1.0, 2.0, 3.0], [0.0, 0.0, 0.0])));
+    }
+
+    #[test]
+    fn malformed_lines_are_errors() {
+        let cases = [
+            ("before_section", "eye = 1 2 3\n[a]\ntarget = 0 0 0\n", ":1:"),
+            ("no_equals", "[a]\neye 1 2 3\ntarget = 0 0 0\n", ":2:"),
+            ("unknown_key", "[a]\neye = 1 2 3\ntraget = 0 0 0\n", ":3:"),
+            ("bad_number", "[a]\neye = 1 2 3\ntarget = 0 0 0\nfovy = wide\n", ":4:"),
+            ("short_vector", "[a]\neye = 1 2\ntarget = 0 0 0\n", ":2:"),
+            ("long_vector", "[a]\neye = 1 2 3 4\ntarget = 0 0 0\n", ":2:"),
+            ("unclosed_name", "[a\neye = 1 2 3\ntarget = 0 0 0\n", ":1:"),
+            ("empty_name", "[ ]\neye = 1 2 3\ntarget = 0 0 0\n", ":1:"),
+            ("no_target", "[a]\neye = 1 2 3\n[b]\neye = 1 2 3\ntarget = 0 0 0\n", ":1:"),
+        ];
+        for (name, contents, line) in cases {
+            let error = format!("{:#}", load_str(name, contents).err().unwrap_or_else(|| panic!("{} should fail", name)));
+            assert!(error.contains(line), "{}: {} doesn't name line {}", name, error, line);
+        }
+    }
+}
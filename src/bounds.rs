@@ -0,0 +1,59 @@
+use cgmath::{EuclideanSpace, InnerSpace};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Aabb {
+    pub min: cgmath::Point3<f32>,
+    pub max: cgmath::Point3<f32>,
+}
+
+impl Aabb {
+    pub fn from_points<I>(points: I) -> Option<Self>
+    where
+        I: IntoIterator<Item = cgmath::Point3<f32>>,
+    {
+        let mut points = points.into_iter();
+        let first = points.next()?;
+        Some(points.fold(Self { min: first, max: first }, |aabb, p| Self {
+            min: cgmath::Point3::new(aabb.min.x.min(p.x), aabb.min.y.min(p.y), aabb.min.z.min(p.z)),
+            max: cgmath::Point3::new(aabb.max.x.max(p.x), aabb.max.y.max(p.y), aabb.max.z.max(p.z)),
+        }))
+    }
+
+    pub fn center(&self) -> cgmath::Point3<f32> {
+        self.min.midpoint(self.max)
+    }
+
+    pub fn half_extents(&self) -> cgmath::Vector3<f32> {
+        (self.max - self.min) * 0.5
+    }
+
+    // Box around this one after `transform` (Arvo's method), which is only
+    // as tight as the original for pure translations
+    pub fn transformed(&self, transform: &cgmath::Matrix4<f32>) -> Aabb {
+        use cgmath::Transform;
+        let center = transform.transform_point(self.center());
+        let h = self.half_extents();
+        let half_extents = cgmath::Vector3::new(
+            transform.x.x.abs() * h.x + transform.y.x.abs() * h.y + transform.z.x.abs() * h.z,
+            transform.x.y.abs() * h.x + transform.y.y.abs() * h.y + transform.z.y.abs() * h.z,
+            transform.x.z.abs() * h.x + transform.y.z.abs() * h.y + transform.z.z.abs() * h.z,
+        );
+        Aabb {
+            min: center - half_extents,
+            max: center + half_extents,
+        }
+    }
+
+    pub fn bounding_sphere(&self) -> Sphere {
+        Sphere {
+            center: self.center(),
+            radius: self.half_extents().magnitude(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Sphere {
+    pub center: cgmath::Point3<f32>,
+    pub radius: f32,
+}
@@ -1,6 +1,15 @@
+use std::time::Duration;
+
 use cgmath::SquareMatrix;
-use winit::{event::{ElementState, KeyEvent, WindowEvent}, keyboard::{KeyCode, PhysicalKey}};
+use winit::{
+    dpi::{PhysicalPosition, PhysicalSize},
+    event::{DeviceEvent, ElementState, KeyEvent, MouseButton, MouseScrollDelta, WindowEvent},
+    keyboard::{KeyCode, PhysicalKey},
+};
+
+use crate::{bounds::Sphere, picking::Ray};
 
+#[derive(Debug, Clone, Copy)]
 pub struct Camera {
     pub eye: cgmath::Point3<f32>,
     pub target: cgmath::Point3<f32>,
@@ -9,46 +18,359 @@ pub struct Camera {
     pub fovy: f32,
     pub znear: f32,
     pub zfar: f32,
+    pub projection: Projection,
+    pub depth_mode: DepthMode,
 }
 
 impl Camera {
+    pub fn build_view_matrix(&self) -> cgmath::Matrix4<f32> {
+        cgmath::Matrix4::look_at_rh(self.eye, self.target, self.up)
+    }
+
+    pub fn build_projection_matrix(&self) -> cgmath::Matrix4<f32> {
+        if let (DepthMode::ReverseZ, Projection::Perspective) = (self.depth_mode, self.projection) {
+            return reverse_z_infinite_perspective(self.fovy, self.aspect, self.znear);
+        }
+
+        let proj = match self.projection {
+            Projection::Perspective => {
+                cgmath::perspective(cgmath::Deg(self.fovy), self.aspect, self.znear, self.zfar)
+            },
+            Projection::Orthographic(OrthographicSize::Height(height)) => {
+                let half_height = height / 2.0;
+                let half_width = half_height * self.aspect;
+                cgmath::ortho(-half_width, half_width, -half_height, half_height, self.znear, self.zfar)
+            },
+        };
+
+        match self.depth_mode {
+            DepthMode::Standard => OPEN_GL_TO_WGPU_MATRIX * proj,
+            DepthMode::ReverseZ => REVERSE_Z_MATRIX * OPEN_GL_TO_WGPU_MATRIX * proj,
+        }
+    }
+
     pub fn build_view_projection_matrix(&self) -> cgmath::Matrix4<f32> {
-        let view = cgmath::Matrix4::look_at_rh(self.eye, self.target, self.up);
-        let proj = cgmath::perspective(cgmath::Deg(self.fovy), self.aspect, self.znear, self.zfar);
+        self.build_projection_matrix() * self.build_view_matrix()
+    }
+
+    // World-space ray through the center of `pixel`, with (0, 0) at the top
+    // left of the viewport. None when there's no ray to be had, such as for a
+    // minimised window or a zero-sized orthographic view
+    pub fn screen_ray(&self, pixel: PhysicalPosition<f64>, viewport_size: PhysicalSize<u32>) -> Option<Ray> {
+        use cgmath::{EuclideanSpace, InnerSpace};
+        if viewport_size.width == 0 || viewport_size.height == 0 {
+            return None;
+        }
+        let x = (2.0 * pixel.x / viewport_size.width as f64 - 1.0) as f32;
+        let y = (1.0 - 2.0 * pixel.y / viewport_size.height as f64) as f32;
+
+        // The second point sits halfway through the depth range rather than
+        // on the far plane, which is at infinity with reverse-Z
+        let near_depth = match self.depth_mode {
+            DepthMode::Standard => 0.0,
+            DepthMode::ReverseZ => 1.0,
+        };
+        let inv_view_proj = self.build_view_projection_matrix().invert()?;
+        let unproject = |depth: f32| {
+            let p = inv_view_proj * cgmath::Vector4::new(x, y, depth, 1.0);
+            cgmath::Point3::from_vec(p.truncate() / p.w)
+        };
+
+        let near = unproject(near_depth);
+        let direction = unproject(0.5) - near;
+        let usable = near.to_vec().magnitude2().is_finite() && direction.magnitude2().is_finite() && direction.magnitude2() > 0.0;
+        usable.then(|| Ray::new(near, direction))
+    }
+
+    // Pixel position of a world-space point, None when it's behind the camera
+    pub fn project(&self, point: cgmath::Point3<f32>, viewport_size: PhysicalSize<u32>) -> Option<PhysicalPosition<f64>> {
+        let clip = self.build_view_projection_matrix() * point.to_homogeneous();
+        if clip.w <= f32::EPSILON {
+            return None;
+        }
+        let x = (clip.x / clip.w + 1.0) / 2.0 * viewport_size.width as f32;
+        let y = (1.0 - clip.y / clip.w) / 2.0 * viewport_size.height as f32;
+        Some(PhysicalPosition::new(x as f64, y as f64))
+    }
+
+    // Re-expresses a rotation given in view space (x right, y up, z towards
+    // the viewer) in world space
+    pub fn view_rotation_to_world(&self, rotation: cgmath::Quaternion<f32>) -> cgmath::Quaternion<f32> {
+        use cgmath::Rotation;
+        let view = self.build_view_matrix();
+        let world_to_view = cgmath::Quaternion::from(cgmath::Matrix3::from_cols(
+            view.x.truncate(),
+            view.y.truncate(),
+            view.z.truncate(),
+        ));
+        world_to_view.invert() * rotation * world_to_view
+    }
+
+    // Copy of this camera moved along its current view direction so `sphere`
+    // fits inside both the vertical and horizontal field of view
+    pub fn framed(&self, sphere: &Sphere) -> Camera {
+        use cgmath::InnerSpace;
+        let half_fovy = self.fovy.to_radians() / 2.0;
+        let half_fovx = (half_fovy.tan() * self.aspect).atan();
+        let radius = sphere.radius.max(f32::EPSILON);
+        let distance = radius / half_fovy.min(half_fovx).sin();
+
+        let direction = self.eye - self.target;
+        let direction = if direction.magnitude2() > f32::EPSILON {
+            direction.normalize()
+        } else {
+            cgmath::Vector3::unit_z()
+        };
+
+        let projection = match self.projection {
+            Projection::Perspective => Projection::Perspective,
+            Projection::Orthographic(_) => {
+                Projection::Orthographic(OrthographicSize::Height(2.0 * radius * (1.0 / self.aspect).max(1.0)))
+            },
+        };
+
+        Camera {
+            eye: sphere.center + direction * distance,
+            target: sphere.center,
+            projection,
+            ..*self
+        }
+    }
+
+    // Switches between perspective and orthographic so that the plane through
+    // `target` keeps the same size on screen
+    pub fn toggle_projection(&mut self) {
+        use cgmath::InnerSpace;
+        let tan_half_fovy = (self.fovy.to_radians() / 2.0).tan();
+        let offset = self.eye - self.target;
+
+        self.projection = match self.projection {
+            Projection::Perspective => {
+                Projection::Orthographic(OrthographicSize::Height(2.0 * offset.magnitude() * tan_half_fovy))
+            },
+            Projection::Orthographic(size) => {
+                let distance = (size.height() / (2.0 * tan_half_fovy)).max(MIN_ORBIT_RADIUS);
+                self.eye = self.target + offset.normalize() * distance;
+                Projection::Perspective
+            },
+        };
+    }
+}
 
-        return OPEN_GL_TO_WGPU_MATRIX * proj * view;
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Projection {
+    Perspective,
+    Orthographic(OrthographicSize),
+}
+
+impl Projection {
+    // Orthographic views don't change size when the eye moves, so zooming
+    // has to scale the view volume instead
+    pub fn zoomed(self, factor: f32) -> Self {
+        match self {
+            Projection::Perspective => Projection::Perspective,
+            Projection::Orthographic(OrthographicSize::Height(height)) => {
+                Projection::Orthographic(OrthographicSize::Height(height * factor))
+            },
+        }
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OrthographicSize {
+    // Width follows from `Camera::aspect`
+    Height(f32),
+}
+
+impl OrthographicSize {
+    pub fn height(&self) -> f32 {
+        match self {
+            OrthographicSize::Height(height) => *height,
+        }
+    }
+}
+
+// cgmath matrices are column-major, so each line below is a column
 #[rustfmt::skip]
 pub const OPEN_GL_TO_WGPU_MATRIX: cgmath::Matrix4<f32> = cgmath::Matrix4::new(
     1.0, 0.0, 0.0, 0.0,
     0.0, 1.0, 0.0, 0.0,
-    0.0, 0.0, 0.5, 0.5,
-    0.0, 0.0, 0.0, 1.0,
+    0.0, 0.0, 0.5, 0.0,
+    0.0, 0.0, 0.5, 1.0,
+);
+
+// Flips wgpu depth from [0, 1] to [1, 0]
+#[rustfmt::skip]
+pub const REVERSE_Z_MATRIX: cgmath::Matrix4<f32> = cgmath::Matrix4::new(
+    1.0, 0.0,  0.0, 0.0,
+    0.0, 1.0,  0.0, 0.0,
+    0.0, 0.0, -1.0, 0.0,
+    0.0, 0.0,  1.0, 1.0,
 );
 
+// Depth is znear / distance: 1.0 at the near plane, approaching 0.0 at
+// infinity, which spreads float precision evenly over the whole range
+pub fn reverse_z_infinite_perspective(fovy: f32, aspect: f32, znear: f32) -> cgmath::Matrix4<f32> {
+    let f = 1.0 / (fovy.to_radians() / 2.0).tan();
+    #[rustfmt::skip]
+    let proj = cgmath::Matrix4::new(
+        f / aspect, 0.0, 0.0,    0.0,
+        0.0,        f,   0.0,    0.0,
+        0.0,        0.0, 0.0,   -1.0,
+        0.0,        0.0, znear,  0.0,
+    );
+    proj
+}
+
+// Reverse-Z pairs with a depth buffer cleared to 0.0 and tested with
+// `Greater`. Perspective cameras also drop the far plane entirely; orthographic
+// ones keep `zfar` since an infinite box can't be mapped to [0, 1]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DepthMode {
+    Standard,
+    ReverseZ,
+}
+
+impl DepthMode {
+    pub fn toggled(&self) -> Self {
+        match self {
+            DepthMode::Standard => DepthMode::ReverseZ,
+            DepthMode::ReverseZ => DepthMode::Standard,
+        }
+    }
+
+    pub fn compare_function(&self) -> wgpu::CompareFunction {
+        match self {
+            DepthMode::Standard => wgpu::CompareFunction::Less,
+            DepthMode::ReverseZ => wgpu::CompareFunction::Greater,
+        }
+    }
+
+    pub fn clear_value(&self) -> f32 {
+        match self {
+            DepthMode::Standard => 1.0,
+            DepthMode::ReverseZ => 0.0,
+        }
+    }
+}
+
+// Must match camera.wgsl
 #[repr(C)]
 #[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct CameraUniform {
     view_proj: [[f32; 4]; 4],
+    view: [[f32; 4]; 4],
+    proj: [[f32; 4]; 4],
+    inv_view: [[f32; 4]; 4],
+    inv_proj: [[f32; 4]; 4],
+    inv_view_proj: [[f32; 4]; 4],
+    eye_position: [f32; 4],
+    viewport: [f32; 4],
+    znear: f32,
+    zfar: f32,
+    time: f32,
+    _padding: f32,
 }
 
 impl CameraUniform {
     pub fn new() -> Self {
-        use cgmath::SquareMatrix;
+        let identity: [[f32; 4]; 4] = cgmath::Matrix4::identity().into();
         Self {
-            view_proj: cgmath::Matrix4::identity().into(),
+            view_proj: identity,
+            view: identity,
+            proj: identity,
+            inv_view: identity,
+            inv_proj: identity,
+            inv_view_proj: identity,
+            eye_position: [0.0, 0.0, 0.0, 1.0],
+            viewport: [1.0, 1.0, 1.0, 1.0],
+            znear: 0.0,
+            zfar: 1.0,
+            time: 0.0,
+            _padding: 0.0,
+        }
+    }
+
+    pub fn update_view_proj(&mut self, camera: &Camera, viewport_size: PhysicalSize<u32>, time: f32) {
+        let view = camera.build_view_matrix();
+        let proj = camera.build_projection_matrix();
+        let view_proj = proj * view;
+        let identity = cgmath::Matrix4::identity();
+
+        self.view_proj = view_proj.into();
+        self.view = view.into();
+        self.proj = proj.into();
+        self.inv_view = view.invert().unwrap_or(identity).into();
+        self.inv_proj = proj.invert().unwrap_or(identity).into();
+        self.inv_view_proj = view_proj.invert().unwrap_or(identity).into();
+        self.eye_position = camera.eye.to_homogeneous().into();
+
+        let width = viewport_size.width.max(1) as f32;
+        let height = viewport_size.height.max(1) as f32;
+        self.viewport = [width, height, 1.0 / width, 1.0 / height];
+
+        self.znear = camera.znear;
+        self.zfar = camera.zfar;
+        self.time = time;
+    }
+}
+
+// Optional smoothing for controller velocities. `acceleration` is in units per
+// second squared and `None` snaps straight to the input velocity. `damping` is
+// an exponential decay rate per second used once input stops; without it the
+// camera stops under `acceleration` (or immediately)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MotionSmoothing {
+    pub acceleration: Option<f32>,
+    pub damping: Option<f32>,
+}
+
+impl MotionSmoothing {
+    pub const NONE: Self = Self {
+        acceleration: None,
+        damping: None,
+    };
+
+    fn step<V>(&self, velocity: V, desired: V, dt: f32) -> V
+    where
+        V: cgmath::InnerSpace<Scalar = f32>,
+    {
+        if desired.is_zero() {
+            if let Some(damping) = self.damping {
+                let damped = velocity * (-damping * dt).exp();
+                return if damped.magnitude2() < 1e-8 { V::zero() } else { damped };
+            }
+        }
+
+        match self.acceleration {
+            Some(acceleration) => {
+                let diff = desired - velocity;
+                let max_change = acceleration * dt;
+                if diff.magnitude() <= max_change {
+                    desired
+                } else {
+                    velocity + diff.normalize() * max_change
+                }
+            },
+            None => desired,
         }
     }
 
-    pub fn update_view_proj(&mut self, camera: &Camera) {
-        self.view_proj = camera.build_view_projection_matrix().into();
+    // Fraction of an impulse released over `dt` when it decays at `damping`,
+    // summing to 1 over any sequence of frames
+    fn impulse_fraction(&self, dt: f32) -> f32 {
+        match self.damping {
+            Some(damping) => 1.0 - (-damping * dt).exp(),
+            None => 1.0,
+        }
     }
 }
 
 pub struct CameraController {
     speed: f32,
+    smoothing: MotionSmoothing,
+    // x is orbiting right, y is moving towards the target
+    velocity: cgmath::Vector2<f32>,
     is_forward_pressed: bool,
     is_backward_pressed: bool,
     is_left_pressed: bool,
@@ -59,6 +381,8 @@ impl CameraController {
     pub fn new(speed: f32) -> Self {
         Self {
             speed,
+            smoothing: MotionSmoothing::NONE,
+            velocity: cgmath::Vector2::new(0.0, 0.0),
             is_forward_pressed: false,
             is_backward_pressed: false,
             is_left_pressed: false,
@@ -66,6 +390,21 @@ impl CameraController {
         }
     }
 
+    pub fn with_smoothing(mut self, smoothing: MotionSmoothing) -> Self {
+        self.smoothing = smoothing;
+        self
+    }
+
+    // Releases get sent to whichever controller is active, so one switched
+    // away from would otherwise keep moving when it comes back
+    pub fn release_all(&mut self) {
+        self.velocity = cgmath::Vector2::new(0.0, 0.0);
+        self.is_forward_pressed = false;
+        self.is_backward_pressed = false;
+        self.is_left_pressed = false;
+        self.is_right_pressed = false;
+    }
+
     pub fn process_events(&mut self, event: &WindowEvent) -> bool {
         match event {
             WindowEvent::KeyboardInput {
@@ -82,7 +421,7 @@ impl CameraController {
                         self.is_forward_pressed = is_pressed;
                         true
                     },
-                    KeyCode::KeyA | KeyCode::ArrowDown => {
+                    KeyCode::KeyA | KeyCode::ArrowLeft => {
                         self.is_left_pressed = is_pressed;
                         true
                     },
@@ -101,29 +440,533 @@ impl CameraController {
         }
     }
 
-    pub fn update_camera(&self, camera: &mut Camera) {
+    pub fn update_camera(&mut self, camera: &mut Camera, dt: Duration) {
         use cgmath::InnerSpace;
+        let dt = dt.as_secs_f32();
+
+        let mut desired = cgmath::Vector2::new(0.0, 0.0);
+        if self.is_forward_pressed {
+            desired.y += self.speed;
+        }
+        if self.is_backward_pressed {
+            desired.y -= self.speed;
+        }
+        if self.is_right_pressed {
+            desired.x += self.speed;
+        }
+        if self.is_left_pressed {
+            desired.x -= self.speed;
+        }
+        self.velocity = self.smoothing.step(self.velocity, desired, dt);
+        let forward_step = self.velocity.y * dt;
+        let right_step = self.velocity.x * dt;
+
         let forward = camera.target - camera.eye;
         let forward_norm = forward.normalize();
         let forward_mag = forward.magnitude();
 
-        if self.is_forward_pressed && forward_mag > self.speed {
-            camera.eye += forward_norm * self.speed;
-        }
-        if self.is_backward_pressed {
-            camera.eye -= forward_norm * self.speed;
+        if forward_mag > forward_step {
+            camera.eye += forward_norm * forward_step;
+        } else {
+            self.velocity.y = 0.0;
         }
+        camera.projection = camera.projection.zoomed((camera.target - camera.eye).magnitude() / forward_mag);
 
         let right = forward_norm.cross(camera.up);
 
         let forward = camera.target - camera.eye;
         let forward_mag = forward.magnitude();
 
+        if right_step != 0.0 {
+            camera.eye = camera.target - (forward + right * right_step).normalize() * forward_mag;
+        }
+    }
+}
+
+// Eases the camera from one placement to another. Eye, target, up, fovy and
+// clip planes are interpolated; orthographic heights too when both ends use one
+pub struct CameraTransition {
+    from: Camera,
+    to: Camera,
+    elapsed: f32,
+    duration: f32,
+}
+
+impl CameraTransition {
+    pub fn new(from: Camera, to: Camera, duration: Duration) -> Self {
+        Self {
+            from,
+            to,
+            elapsed: 0.0,
+            duration: duration.as_secs_f32(),
+        }
+    }
+
+    // Returns true once the camera has arrived
+    pub fn update(&mut self, camera: &mut Camera, dt: Duration) -> bool {
+        use cgmath::VectorSpace;
+        self.elapsed += dt.as_secs_f32();
+        let t = if self.duration > 0.0 { (self.elapsed / self.duration).min(1.0) } else { 1.0 };
+        let t = t * t * (3.0 - 2.0 * t);
+
+        let from = &self.from;
+        let to = &self.to;
+        camera.eye = from.eye + (to.eye - from.eye) * t;
+        camera.target = from.target + (to.target - from.target) * t;
+        camera.up = from.up.lerp(to.up, t);
+        camera.fovy = from.fovy + (to.fovy - from.fovy) * t;
+        camera.znear = from.znear + (to.znear - from.znear) * t;
+        camera.zfar = from.zfar + (to.zfar - from.zfar) * t;
+        camera.projection = match (from.projection, to.projection) {
+            (
+                Projection::Orthographic(OrthographicSize::Height(a)),
+                Projection::Orthographic(OrthographicSize::Height(b)),
+            ) => Projection::Orthographic(OrthographicSize::Height(a + (b - a) * t)),
+            _ => to.projection,
+        };
+
+        self.elapsed >= self.duration
+    }
+}
+
+// Keeps the orbit from reaching the poles, where `eye - target` would line up
+// with `Camera::up` and `look_at_rh` flips the view
+const MIN_POLAR_ANGLE: f32 = 0.01;
+const MIN_ORBIT_RADIUS: f32 = 0.1;
+
+pub struct OrbitCameraController {
+    rotate_speed: f32,
+    zoom_speed: f32,
+    pan_speed: f32,
+    smoothing: MotionSmoothing,
+    is_rotating: bool,
+    is_panning: bool,
+    last_cursor: Option<PhysicalPosition<f64>>,
+    rotate_delta: cgmath::Vector2<f32>,
+    pan_delta: cgmath::Vector2<f32>,
+    scroll: f32,
+}
+
+impl OrbitCameraController {
+    pub fn new(rotate_speed: f32, zoom_speed: f32, pan_speed: f32) -> Self {
+        Self {
+            rotate_speed,
+            zoom_speed,
+            pan_speed,
+            smoothing: MotionSmoothing::NONE,
+            is_rotating: false,
+            is_panning: false,
+            last_cursor: None,
+            rotate_delta: cgmath::Vector2::new(0.0, 0.0),
+            pan_delta: cgmath::Vector2::new(0.0, 0.0),
+            scroll: 0.0,
+        }
+    }
+
+    // Only `damping` applies here: wheel zoom is spread out over time instead
+    // of jumping, rotation and panning follow the cursor directly
+    pub fn with_smoothing(mut self, smoothing: MotionSmoothing) -> Self {
+        self.smoothing = smoothing;
+        self
+    }
+
+    pub fn release_all(&mut self) {
+        self.is_rotating = false;
+        self.is_panning = false;
+        self.rotate_delta = cgmath::Vector2::new(0.0, 0.0);
+        self.pan_delta = cgmath::Vector2::new(0.0, 0.0);
+        self.scroll = 0.0;
+    }
+
+    pub fn process_events(&mut self, event: &WindowEvent) -> bool {
+        match event {
+            WindowEvent::MouseInput { state, button: MouseButton::Left, .. } => {
+                self.is_rotating = *state == ElementState::Pressed;
+                true
+            },
+            WindowEvent::MouseInput { state, button: MouseButton::Middle, .. } => {
+                self.is_panning = *state == ElementState::Pressed;
+                true
+            },
+            WindowEvent::CursorMoved { position, .. } => {
+                if let Some(last) = self.last_cursor {
+                    let delta = cgmath::Vector2::new(
+                        (position.x - last.x) as f32,
+                        (position.y - last.y) as f32,
+                    );
+                    if self.is_rotating {
+                        self.rotate_delta += delta;
+                    }
+                    if self.is_panning {
+                        self.pan_delta += delta;
+                    }
+                }
+                self.last_cursor = Some(*position);
+                self.is_rotating || self.is_panning
+            },
+            WindowEvent::CursorLeft { .. } => {
+                self.last_cursor = None;
+                false
+            },
+            WindowEvent::MouseWheel { delta, .. } => {
+                self.scroll += match delta {
+                    MouseScrollDelta::LineDelta(_, y) => *y,
+                    // roughly one line per 100 pixels of trackpad scroll
+                    MouseScrollDelta::PixelDelta(position) => position.y as f32 / 100.0,
+                };
+                true
+            },
+            _ => false,
+        }
+    }
+
+    pub fn update_camera(&mut self, camera: &mut Camera, dt: Duration) {
+        use cgmath::{InnerSpace, Rotation, Rotation3};
+        let up = camera.up.normalize();
+        let mut offset = camera.eye - camera.target;
+
+        if self.rotate_delta.x != 0.0 {
+            let yaw = cgmath::Quaternion::from_axis_angle(up, cgmath::Rad(-self.rotate_delta.x * self.rotate_speed));
+            offset = yaw.rotate_vector(offset);
+        }
+
+        let axis = offset.cross(up);
+        if axis.magnitude2() > f32::EPSILON {
+            let polar = (offset.normalize().dot(up)).clamp(-1.0, 1.0).acos();
+            let new_polar = (polar - self.rotate_delta.y * self.rotate_speed)
+                .clamp(MIN_POLAR_ANGLE, std::f32::consts::PI - MIN_POLAR_ANGLE);
+            // rotating around `offset x up` moves the eye towards `up`
+            let pitch = cgmath::Quaternion::from_axis_angle(axis.normalize(), cgmath::Rad(polar - new_polar));
+            offset = pitch.rotate_vector(offset);
+        }
+
+        let scroll = self.scroll * self.smoothing.impulse_fraction(dt.as_secs_f32());
+        if scroll.abs() > 1e-4 {
+            let radius = offset.magnitude();
+            let new_radius = (radius * (1.0 - self.zoom_speed).powf(scroll)).max(MIN_ORBIT_RADIUS);
+            offset = offset.normalize() * new_radius;
+            camera.projection = camera.projection.zoomed(new_radius / radius);
+        }
+
+        if self.pan_delta.x != 0.0 || self.pan_delta.y != 0.0 {
+            let forward = -offset.normalize();
+            let right = forward.cross(up).normalize();
+            let screen_up = right.cross(forward);
+            let scale = offset.magnitude() * self.pan_speed;
+            let pan = (screen_up * self.pan_delta.y - right * self.pan_delta.x) * scale;
+            camera.target += pan;
+        }
+
+        camera.eye = camera.target + offset;
+
+        self.rotate_delta = cgmath::Vector2::new(0.0, 0.0);
+        self.pan_delta = cgmath::Vector2::new(0.0, 0.0);
+        self.scroll = if scroll.abs() > 1e-4 { self.scroll - scroll } else { 0.0 };
+    }
+}
+
+// Free-fly camera is Y-up; pitch stops just short of straight up/down
+const MAX_PITCH: f32 = std::f32::consts::FRAC_PI_2 - 0.001;
+
+pub struct FreeFlyCamera {
+    pub position: cgmath::Point3<f32>,
+    pub yaw: cgmath::Rad<f32>,
+    pub pitch: cgmath::Rad<f32>,
+}
+
+impl FreeFlyCamera {
+    pub fn from_camera(camera: &Camera) -> Self {
+        use cgmath::InnerSpace;
+        let forward = (camera.target - camera.eye).normalize();
+        Self {
+            position: camera.eye,
+            yaw: cgmath::Rad(forward.z.atan2(forward.x)),
+            pitch: cgmath::Rad(forward.y.clamp(-1.0, 1.0).asin().clamp(-MAX_PITCH, MAX_PITCH)),
+        }
+    }
+
+    pub fn forward(&self) -> cgmath::Vector3<f32> {
+        let (sin_yaw, cos_yaw) = self.yaw.0.sin_cos();
+        let (sin_pitch, cos_pitch) = self.pitch.0.sin_cos();
+        cgmath::Vector3::new(cos_pitch * cos_yaw, sin_pitch, cos_pitch * sin_yaw)
+    }
+
+    // Writes position and look direction back into `camera`, keeping the
+    // eye-target distance so switching back to another mode frames the same
+    pub fn apply(&self, camera: &mut Camera) {
+        use cgmath::InnerSpace;
+        let distance = (camera.target - camera.eye).magnitude().max(MIN_ORBIT_RADIUS);
+        camera.eye = self.position;
+        camera.target = self.position + self.forward() * distance;
+        camera.up = cgmath::Vector3::unit_y();
+    }
+}
+
+pub struct FreeFlyController {
+    speed: f32,
+    sensitivity: f32,
+    smoothing: MotionSmoothing,
+    velocity: cgmath::Vector3<f32>,
+    is_forward_pressed: bool,
+    is_backward_pressed: bool,
+    is_left_pressed: bool,
+    is_right_pressed: bool,
+    is_up_pressed: bool,
+    is_down_pressed: bool,
+    is_looking: bool,
+    is_grabbed: bool,
+    look_delta: cgmath::Vector2<f32>,
+}
+
+impl FreeFlyController {
+    pub fn new(speed: f32, sensitivity: f32) -> Self {
+        Self {
+            speed,
+            sensitivity,
+            smoothing: MotionSmoothing::NONE,
+            velocity: cgmath::Vector3::new(0.0, 0.0, 0.0),
+            is_forward_pressed: false,
+            is_backward_pressed: false,
+            is_left_pressed: false,
+            is_right_pressed: false,
+            is_up_pressed: false,
+            is_down_pressed: false,
+            is_looking: false,
+            is_grabbed: false,
+            look_delta: cgmath::Vector2::new(0.0, 0.0),
+        }
+    }
+
+    pub fn with_smoothing(mut self, smoothing: MotionSmoothing) -> Self {
+        self.smoothing = smoothing;
+        self
+    }
+
+    pub fn is_grabbed(&self) -> bool {
+        self.is_grabbed
+    }
+
+    // With the cursor grabbed every mouse motion turns the camera, otherwise
+    // only while the left button is held
+    pub fn set_grabbed(&mut self, grabbed: bool) {
+        self.is_grabbed = grabbed;
+    }
+
+    // Leaves the cursor grab alone, that's owned by the window
+    pub fn release_all(&mut self) {
+        self.velocity = cgmath::Vector3::new(0.0, 0.0, 0.0);
+        self.is_forward_pressed = false;
+        self.is_backward_pressed = false;
+        self.is_left_pressed = false;
+        self.is_right_pressed = false;
+        self.is_up_pressed = false;
+        self.is_down_pressed = false;
+        self.is_looking = false;
+        self.look_delta = cgmath::Vector2::new(0.0, 0.0);
+    }
+
+    pub fn process_events(&mut self, event: &WindowEvent) -> bool {
+        match event {
+            WindowEvent::KeyboardInput {
+                event: KeyEvent {
+                    state,
+                    physical_key: PhysicalKey::Code(keycode),
+                    ..
+                },
+                ..
+            } => {
+                let is_pressed = *state == ElementState::Pressed;
+                match keycode {
+                    KeyCode::KeyW | KeyCode::ArrowUp => {
+                        self.is_forward_pressed = is_pressed;
+                        true
+                    },
+                    KeyCode::KeyA | KeyCode::ArrowLeft => {
+                        self.is_left_pressed = is_pressed;
+                        true
+                    },
+                    KeyCode::KeyS | KeyCode::ArrowDown => {
+                        self.is_backward_pressed = is_pressed;
+                        true
+                    },
+                    KeyCode::KeyD | KeyCode::ArrowRight => {
+                        self.is_right_pressed = is_pressed;
+                        true
+                    },
+                    KeyCode::KeyE => {
+                        self.is_up_pressed = is_pressed;
+                        true
+                    },
+                    KeyCode::KeyQ => {
+                        self.is_down_pressed = is_pressed;
+                        true
+                    },
+                    _ => false,
+                }
+            },
+            WindowEvent::MouseInput { state, button: MouseButton::Left, .. } => {
+                self.is_looking = *state == ElementState::Pressed;
+                true
+            },
+            _ => false,
+        }
+    }
+
+    pub fn process_device_events(&mut self, event: &DeviceEvent) -> bool {
+        match event {
+            DeviceEvent::MouseMotion { delta } if self.is_grabbed || self.is_looking => {
+                self.look_delta += cgmath::Vector2::new(delta.0 as f32, delta.1 as f32);
+                true
+            },
+            _ => false,
+        }
+    }
+
+    pub fn update_camera(&mut self, camera: &mut FreeFlyCamera, dt: Duration) {
+        use cgmath::InnerSpace;
+        camera.yaw += cgmath::Rad(self.look_delta.x * self.sensitivity);
+        camera.pitch = cgmath::Rad((camera.pitch.0 - self.look_delta.y * self.sensitivity).clamp(-MAX_PITCH, MAX_PITCH));
+        self.look_delta = cgmath::Vector2::new(0.0, 0.0);
+
+        let forward = camera.forward();
+        let right = forward.cross(cgmath::Vector3::unit_y()).normalize();
+
+        let mut desired = cgmath::Vector3::new(0.0, 0.0, 0.0);
+        if self.is_forward_pressed {
+            desired += forward;
+        }
+        if self.is_backward_pressed {
+            desired -= forward;
+        }
         if self.is_right_pressed {
-            camera.eye = camera.target - (forward + right * self.speed).normalize() * forward_mag;
+            desired += right;
         }
         if self.is_left_pressed {
-            camera.eye = camera.target - (forward - right * self.speed).normalize() * forward_mag;
+            desired -= right;
+        }
+        if self.is_up_pressed {
+            desired += cgmath::Vector3::unit_y();
+        }
+        if self.is_down_pressed {
+            desired -= cgmath::Vector3::unit_y();
+        }
+        if desired.magnitude2() > 0.0 {
+            desired = desired.normalize() * self.speed;
+        }
+
+        let dt = dt.as_secs_f32();
+        self.velocity = self.smoothing.step(self.velocity, desired, dt);
+        camera.position += self.velocity * dt;
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CameraMode {
+    Keyboard,
+    Orbit,
+    FreeFly,
+}
+
+impl CameraMode {
+    pub fn next(&self) -> Self {
+        match self {
+            CameraMode::Keyboard => CameraMode::Orbit,
+            CameraMode::Orbit => CameraMode::FreeFly,
+            CameraMode::FreeFly => CameraMode::Keyboard,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn camera(projection: Projection, depth_mode: DepthMode) -> Camera {
+        Camera {
+            eye: cgmath::Point3::new(0.0, 0.0, 0.0),
+            target: cgmath::Point3::new(0.0, 0.0, -1.0),
+            up: cgmath::Vector3::unit_y(),
+            aspect: 1.5,
+            fovy: 60.0,
+            znear: 1.0,
+            zfar: 100.0,
+            projection,
+            depth_mode,
+        }
+    }
+
+    // Depth after the perspective divide of a point `distance` in front
+    fn depth(camera: &Camera, distance: f32) -> f32 {
+        let clip = camera.build_view_projection_matrix() * cgmath::Vector4::new(0.0, 0.0, -distance, 1.0);
+        clip.z / clip.w
+    }
+
+    fn assert_near(actual: f32, expected: f32, what: &str) {
+        assert!((actual - expected).abs() < 1e-5, "{} is {}, expected {}", what, actual, expected);
+    }
+
+    #[test]
+    fn standard_depth_runs_from_near_to_far() {
+        for projection in [Projection::Perspective, Projection::Orthographic(OrthographicSize::Height(10.0))] {
+            let camera = camera(projection, DepthMode::Standard);
+            assert_near(depth(&camera, 1.0), 0.0, "near");
+            assert_near(depth(&camera, 100.0), 1.0, "far");
+            let mid = depth(&camera, 10.0);
+            assert!(mid > 0.0 && mid < 1.0, "{:?} mid is {}", projection, mid);
+        }
+    }
+
+    #[test]
+    fn reverse_z_maps_near_to_one_and_far_towards_zero() {
+        let perspective = camera(Projection::Perspective, DepthMode::ReverseZ);
+        assert_near(depth(&perspective, 1.0), 1.0, "near");
+        // znear / distance, with no far plane
+        assert_near(depth(&perspective, 10.0), 0.1, "mid");
+        assert_near(depth(&perspective, 100.0), 0.01, "far");
+        let distant = depth(&perspective, 1.0e6);
+        assert!(distant > 0.0 && distant < 1.0e-5, "distant is {}", distant);
+
+        let orthographic = camera(Projection::Orthographic(OrthographicSize::Height(10.0)), DepthMode::ReverseZ);
+        assert_near(depth(&orthographic, 1.0), 1.0, "orthographic near");
+        assert_near(depth(&orthographic, 100.0), 0.0, "orthographic far");
+        let mid = depth(&orthographic, 10.0);
+        assert!(mid > 0.0 && mid < 1.0, "orthographic mid is {}", mid);
+    }
+
+    #[test]
+    fn screen_ray_through_the_centre() {
+        use cgmath::InnerSpace;
+        let size = PhysicalSize::new(300, 200);
+        let centre = PhysicalPosition::new(150.0, 100.0);
+        for projection in [Projection::Perspective, Projection::Orthographic(OrthographicSize::Height(10.0))] {
+            for depth_mode in [DepthMode::Standard, DepthMode::ReverseZ] {
+                let ray = camera(projection, depth_mode).screen_ray(centre, size).unwrap();
+                assert!((ray.direction - -cgmath::Vector3::unit_z()).magnitude() < 1e-4, "{:?} {:?} {:?}", projection, depth_mode, ray);
+                assert!((ray.origin.z + 1.0).abs() < 1e-4, "{:?} {:?} {:?}", projection, depth_mode, ray);
+            }
+        }
+    }
+
+    #[test]
+    fn no_screen_ray_for_degenerate_views() {
+        let pixel = PhysicalPosition::new(0.0, 0.0);
+        let perspective = camera(Projection::Perspective, DepthMode::Standard);
+        assert_eq!(perspective.screen_ray(pixel, PhysicalSize::new(0, 0)), None);
+        assert_eq!(perspective.screen_ray(pixel, PhysicalSize::new(300, 0)), None);
+        for depth_mode in [DepthMode::Standard, DepthMode::ReverseZ] {
+            let flat = camera(Projection::Orthographic(OrthographicSize::Height(0.0)), depth_mode);
+            assert_eq!(flat.screen_ray(pixel, PhysicalSize::new(300, 200)), None, "{:?}", depth_mode);
+        }
+    }
+
+    #[test]
+    fn reverse_z_keeps_screen_positions() {
+        let point = cgmath::Point3::new(2.0, -1.0, -7.0);
+        let size = PhysicalSize::new(300, 200);
+        for projection in [Projection::Perspective, Projection::Orthographic(OrthographicSize::Height(10.0))] {
+            let standard = camera(projection, DepthMode::Standard).project(point, size).unwrap();
+            let reverse = camera(projection, DepthMode::ReverseZ).project(point, size).unwrap();
+            assert!((standard.x - reverse.x).abs() < 1e-3 && (standard.y - reverse.y).abs() < 1e-3, "{:?}", projection);
         }
     }
 }
@@ -0,0 +1,21 @@
+// Shared camera uniform, prepended to every shader that needs it
+struct CameraUniform {
+    view_proj: mat4x4<f32>,
+    view: mat4x4<f32>,
+    proj: mat4x4<f32>,
+    inv_view: mat4x4<f32>,
+    inv_proj: mat4x4<f32>,
+    inv_view_proj: mat4x4<f32>,
+    // w is always 1.0
+    eye_position: vec4<f32>,
+    // width, height, 1 / width, 1 / height in pixels
+    viewport: vec4<f32>,
+    znear: f32,
+    zfar: f32,
+    // seconds since startup
+    time: f32,
+};
+
+@group(1) @binding(0)
+var<uniform> camera: CameraUniform;
+
@@ -0,0 +1,264 @@
+use std::{
+    ops::{Add, Mul, Sub},
+    path::Path,
+    time::Duration,
+};
+
+use anyhow::*;
+use cgmath::{EuclideanSpace, InnerSpace, Rotation};
+
+use crate::camera::Camera;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Keyframe {
+    pub time: f32,
+    pub eye: cgmath::Point3<f32>,
+    pub target: cgmath::Point3<f32>,
+    pub fovy: f32,
+}
+
+impl Keyframe {
+    pub fn from_camera(camera: &Camera, time: f32) -> Self {
+        Self {
+            time,
+            eye: camera.eye,
+            target: camera.target,
+            fovy: camera.fovy,
+        }
+    }
+}
+
+// Eye positions, look distance and fovy follow a Catmull-Rom spline through
+// the keyframes, the look direction is slerped between them
+pub struct CameraPath {
+    keyframes: Vec<Keyframe>,
+    time: f32,
+    playing: bool,
+    pub looping: bool,
+}
+
+impl CameraPath {
+    pub fn new() -> Self {
+        Self {
+            keyframes: Vec::new(),
+            time: 0.0,
+            playing: false,
+            looping: false,
+        }
+    }
+
+    pub fn keyframes(&self) -> &[Keyframe] {
+        &self.keyframes
+    }
+
+    pub fn add_keyframe(&mut self, keyframe: Keyframe) {
+        let index = self.keyframes.partition_point(|k| k.time <= keyframe.time);
+        self.keyframes.insert(index, keyframe);
+    }
+
+    pub fn clear(&mut self) {
+        self.keyframes.clear();
+        self.time = 0.0;
+        self.playing = false;
+    }
+
+    pub fn duration(&self) -> f32 {
+        match (self.keyframes.first(), self.keyframes.last()) {
+            (Some(first), Some(last)) => last.time - first.time,
+            _ => 0.0,
+        }
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.playing
+    }
+
+    pub fn play(&mut self) {
+        if self.keyframes.len() < 2 {
+            log::warn!("Camera path needs at least two keyframes to play");
+            return;
+        }
+        if self.time >= self.duration() {
+            self.time = 0.0;
+        }
+        self.playing = true;
+    }
+
+    pub fn pause(&mut self) {
+        self.playing = false;
+    }
+
+    pub fn toggle_play(&mut self) {
+        if self.playing {
+            self.pause();
+        } else {
+            self.play();
+        }
+    }
+
+    pub fn update(&mut self, dt: Duration) {
+        if !self.playing {
+            return;
+        }
+
+        let duration = self.duration();
+        self.time += dt.as_secs_f32();
+        if self.time >= duration {
+            if self.looping && duration > 0.0 {
+                self.time %= duration;
+            } else {
+                self.time = duration;
+                self.playing = false;
+            }
+        }
+    }
+
+    // Sample at the current playback time, relative to the first keyframe
+    pub fn sample(&self, up: cgmath::Vector3<f32>) -> Option<Keyframe> {
+        let first = self.keyframes.first()?;
+        let time = first.time + self.time;
+        let last = self.keyframes.len() - 1;
+        let segment = self.keyframes.partition_point(|k| k.time <= time).saturating_sub(1).min(last.saturating_sub(1));
+
+        let k0 = &self.keyframes[segment.saturating_sub(1)];
+        let k1 = &self.keyframes[segment];
+        let k2 = &self.keyframes[(segment + 1).min(last)];
+        let k3 = &self.keyframes[(segment + 2).min(last)];
+
+        let span = k2.time - k1.time;
+        let t = if span > 0.0 { ((time - k1.time) / span).clamp(0.0, 1.0) } else { 0.0 };
+
+        let eye = catmull_rom(k0.eye.to_vec(), k1.eye.to_vec(), k2.eye.to_vec(), k3.eye.to_vec(), t);
+        let distance = catmull_rom(
+            (k0.target - k0.eye).magnitude(),
+            (k1.target - k1.eye).magnitude(),
+            (k2.target - k2.eye).magnitude(),
+            (k3.target - k3.eye).magnitude(),
+            t,
+        );
+        let fovy = catmull_rom(k0.fovy, k1.fovy, k2.fovy, k3.fovy, t);
+
+        // look_at maps the view direction onto +Z, so its inverse takes +Z
+        // back to the interpolated view direction
+        let q1 = cgmath::Quaternion::look_at((k1.target - k1.eye).normalize(), up);
+        let q2 = cgmath::Quaternion::look_at((k2.target - k2.eye).normalize(), up);
+        let forward = q1.slerp(q2, t).invert().rotate_vector(cgmath::Vector3::unit_z());
+
+        let eye = cgmath::Point3::from_vec(eye);
+        Some(Keyframe {
+            time,
+            eye,
+            target: eye + forward * distance.max(f32::EPSILON),
+            fovy: fovy.clamp(1.0, 179.0),
+        })
+    }
+
+    pub fn apply(&self, camera: &mut Camera) {
+        if let Some(keyframe) = self.sample(camera.up) {
+            camera.eye = keyframe.eye;
+            camera.target = keyframe.target;
+            camera.fovy = keyframe.fovy;
+        }
+    }
+
+    // One keyframe per line: time, eye xyz, target xyz, fovy. Blank lines and
+    // lines starting with '#' are ignored
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("Couldn't read camera path {}", path.display()))?;
+
+        let mut camera_path = Self::new();
+        for (number, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let values = line
+                .split_whitespace()
+                .map(|v| v.parse::<f32>())
+                .collect::<std::result::Result<Vec<_>, _>>()
+                .with_context(|| format!("{}:{}: invalid number", path.display(), number + 1))?;
+            if values.len() != 8 {
+                bail!("{}:{}: expected 8 values, found {}", path.display(), number + 1, values.len());
+            }
+
+            camera_path.add_keyframe(Keyframe {
+                time: values[0],
+                eye: cgmath::Point3::new(values[1], values[2], values[3]),
+                target: cgmath::Point3::new(values[4], values[5], values[6]),
+                fovy: values[7],
+            });
+        }
+
+        Ok(camera_path)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        let mut contents = String::from("# time eye.x eye.y eye.z target.x target.y target.z fovy\n");
+        for k in &self.keyframes {
+            contents.push_str(&format!(
+                "{} {} {} {} {} {} {} {}\n",
+                k.time, k.eye.x, k.eye.y, k.eye.z, k.target.x, k.target.y, k.target.z, k.fovy,
+            ));
+        }
+
+        std::fs::write(path, contents)
+            .with_context(|| format!("Couldn't write camera path {}", path.display()))
+    }
+}
+
+fn catmull_rom<T>(p0: T, p1: T, p2: T, p3: T, t: f32) -> T
+where
+    T: Copy + Add<Output = T> + Sub<Output = T> + Mul<f32, Output = T>,
+{
+    let t2 = t * t;
+    let t3 = t2 * t;
+    (p1 * 2.0
+        + (p2 - p0) * t
+        + (p0 * 2.0 - p1 * 5.0 + p2 * 4.0 - p3) * t2
+        + (p1 * 3.0 - p0 - p2 * 3.0 + p3) * t3)
+        * 0.5
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keyframe(time: f32, eye: [f32; 3], target: [f32; 3], fovy: f32) -> Keyframe {
+        Keyframe {
+            time,
+            eye: eye.into(),
+            target: target.into(),
+            fovy,
+        }
+    }
+
+    #[test]
+    fn save_and_load_round_trip() {
+        let mut camera_path = CameraPath::new();
+        camera_path.add_keyframe(keyframe(2.5, [-1.25, 0.1, 3.0e-7], [0.0, 0.0, 0.0], 45.0));
+        camera_path.add_keyframe(keyframe(0.0, [0.0, 1.0, 2.0], [1.0 / 3.0, -2.0, 1.0e6], 30.5));
+        camera_path.add_keyframe(keyframe(4.0, [5.0, 5.0, 5.0], [0.5, 0.25, -0.125], 90.0));
+
+        let path = std::env::temp_dir().join(format!("camera_path_round_trip_{}.txt", std::process::id()));
+        camera_path.save(&path).unwrap();
+        let loaded = CameraPath::load(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.unwrap().keyframes(), camera_path.keyframes());
+    }
+
+    #[test]
+    fn malformed_lines_are_errors() {
+        for (name, contents) in [("count", "0 1 2 3 4 5 6\n"), ("number", "0 1 2 3 4 5 6 x\n")] {
+            let path = std::env::temp_dir().join(format!("camera_path_malformed_{}_{}.txt", name, std::process::id()));
+            std::fs::write(&path, format!("# comment\n\n{}", contents)).unwrap();
+            let loaded = CameraPath::load(&path);
+            std::fs::remove_file(&path).unwrap();
+            let error = format!("{:#}", loaded.err().expect("should fail"));
+            assert!(error.contains(":3:"), "{} doesn't name line 3", error);
+        }
+    }
+}
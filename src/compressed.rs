@@ -0,0 +1,409 @@
+// KTX2 and DDS containers. Both are read into a `CompressedImage` holding the
+// blocks exactly as stored, prebuilt mip levels included, so they can go to
+// the GPU untouched or be decoded on the CPU when the device can't sample them
+
+use anyhow::*;
+
+use crate::texture::ColorSpace;
+
+const KTX2_MAGIC: &[u8] = b"\xABKTX 20\xBB\r\n\x1A\n";
+const DDS_MAGIC: &[u8] = b"DDS ";
+
+pub fn is_container(bytes: &[u8]) -> bool {
+    bytes.starts_with(KTX2_MAGIC) || bytes.starts_with(DDS_MAGIC)
+}
+
+pub struct CompressedImage {
+    pub format: wgpu::TextureFormat,
+    pub width: u32,
+    pub height: u32,
+    // Array layers, counting six for each cube
+    pub layers: u32,
+    pub cube: bool,
+    // Largest first, each holding every layer back to back
+    pub levels: Vec<Vec<u8>>,
+}
+
+impl CompressedImage {
+    // `color_space` only matters for old DDS files, whose formats don't say
+    // whether they are sRGB
+    pub fn load(bytes: &[u8], color_space: ColorSpace) -> Result<Self> {
+        if bytes.starts_with(KTX2_MAGIC) {
+            Self::from_ktx2(bytes)
+        } else if bytes.starts_with(DDS_MAGIC) {
+            Self::from_dds(bytes, color_space)
+        } else {
+            bail!("Not a KTX2 or DDS file")
+        }
+    }
+
+    fn from_ktx2(bytes: &[u8]) -> Result<Self> {
+        let reader = ktx2::Reader::new(bytes).map_err(|e| anyhow!("Invalid KTX2 file: {:?}", e))?;
+        let header = reader.header();
+        if let Some(scheme) = header.supercompression_scheme {
+            bail!("Supercompressed KTX2 files ({:?}) aren't supported", scheme);
+        }
+        if header.pixel_depth > 1 {
+            bail!("3D KTX2 textures aren't supported");
+        }
+        let format = header.format.context("KTX2 file has no format, Basis Universal files aren't supported")?;
+
+        let image = Self {
+            format: ktx2_format(format)?,
+            width: header.pixel_width,
+            height: header.pixel_height.max(1),
+            layers: header.layer_count.max(1) * header.face_count,
+            cube: header.face_count == 6,
+            levels: reader.levels().map(|level| level.to_vec()).collect(),
+        };
+        image.validate()?;
+        Ok(image)
+    }
+
+    // DDS stores each layer with its whole mip chain in turn, so the levels
+    // are gathered back together here
+    fn from_dds(bytes: &[u8], color_space: ColorSpace) -> Result<Self> {
+        let dds = ddsfile::Dds::read(bytes).map_err(|e| anyhow!("Invalid DDS file: {}", e))?;
+        if dds.get_depth() > 1 {
+            bail!("3D DDS textures aren't supported");
+        }
+        let (format, array_size, cube) = match &dds.header10 {
+            Some(header10) => (
+                dxgi_format(header10.dxgi_format)?,
+                header10.array_size.max(1),
+                header10.misc_flag.contains(ddsfile::MiscFlag::TEXTURECUBE),
+            ),
+            None => (
+                legacy_dds_format(&dds, color_space)?,
+                1,
+                dds.header.caps2.contains(ddsfile::Caps2::CUBEMAP),
+            ),
+        };
+
+        let mut image = Self {
+            format,
+            width: dds.get_width(),
+            height: dds.get_height(),
+            layers: if cube { array_size * 6 } else { array_size },
+            cube,
+            levels: Vec::new(),
+        };
+        let level_count = dds.get_num_mipmap_levels().max(1);
+        image.levels = vec![Vec::new(); level_count as usize];
+        let mut data = dds.data.as_slice();
+        for _ in 0..image.layers {
+            for level in 0..level_count {
+                let size = image.layer_size(level);
+                if data.len() < size {
+                    bail!("DDS file ends before the data for level {}", level);
+                }
+                let (layer, rest) = data.split_at(size);
+                image.levels[level as usize].extend_from_slice(layer);
+                data = rest;
+            }
+        }
+        image.validate()?;
+        Ok(image)
+    }
+
+    fn validate(&self) -> Result<()> {
+        // They'd need a CubeArray view, which downlevel and WebGL2 devices
+        // don't have
+        if self.cube && self.layers > 6 {
+            bail!("Cube map arrays aren't supported, found {} cubes", self.layers / 6);
+        }
+        let (block_width, block_height) = self.format.block_dimensions();
+        // wgpu needs the base level to be whole blocks, smaller levels are
+        // padded out
+        if !self.width.is_multiple_of(block_width) || !self.height.is_multiple_of(block_height) {
+            bail!(
+                "{}x{} isn't a multiple of the {}x{} blocks of {:?}",
+                self.width, self.height, block_width, block_height, self.format,
+            );
+        }
+        if self.levels.is_empty() {
+            bail!("Texture has no mip levels");
+        }
+        for (level, data) in self.levels.iter().enumerate() {
+            let expected = self.layer_size(level as u32) * self.layers as usize;
+            if data.len() < expected {
+                bail!("Mip level {} has {} bytes, expected {}", level, data.len(), expected);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn level_size(&self, level: u32) -> (u32, u32) {
+        ((self.width >> level).max(1), (self.height >> level).max(1))
+    }
+
+    // Bytes in one layer of `level`, which always covers whole blocks
+    pub fn layer_size(&self, level: u32) -> usize {
+        let (width, height) = self.level_size(level);
+        let (block_width, block_height) = self.format.block_dimensions();
+        let block_size = self.format.block_copy_size(None).unwrap_or(0);
+        (width.div_ceil(block_width) * height.div_ceil(block_height) * block_size) as usize
+    }
+
+    pub fn layer(&self, level: u32, layer: u32) -> &[u8] {
+        let size = self.layer_size(level);
+        let start = size * layer as usize;
+        &self.levels[level as usize][start..start + size]
+    }
+
+    // Decodes every level to RGBA8, keeping the colour space. BC6H is HDR but
+    // the decoder clamps it to 8 bits. Signed BC4 and BC5 become Rgba8Snorm
+    pub fn decompress(&self) -> Result<Self> {
+        use wgpu::TextureFormat::*;
+        let format = match self.format {
+            Bc4RSnorm | Bc5RgSnorm => Rgba8Snorm,
+            format if format.is_srgb() => Rgba8UnormSrgb,
+            _ => Rgba8Unorm,
+        };
+        let levels = (0..self.levels.len() as u32)
+            .map(|level| {
+                let (width, height) = self.level_size(level);
+                let mut rgba = Vec::with_capacity((width * height * 4 * self.layers) as usize);
+                for layer in 0..self.layers {
+                    if format == Rgba8Snorm {
+                        rgba.extend(decode_signed(self.format, self.layer(level, layer), width, height));
+                        continue;
+                    }
+                    let mut texels = vec![0u32; (width * height) as usize];
+                    decode(self.format, self.layer(level, layer), width as usize, height as usize, &mut texels)
+                        .map_err(|e| anyhow!("Couldn't decompress {:?}: {}", self.format, e))?;
+                    // The decoder packs texels as little-endian BGRA
+                    rgba.extend(texels.iter().flat_map(|texel| {
+                        let [b, g, r, a] = texel.to_le_bytes();
+                        [r, g, b, a]
+                    }));
+                }
+                Ok(rgba)
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Self { format, levels, ..*self })
+    }
+}
+
+fn decode(format: wgpu::TextureFormat, data: &[u8], width: usize, height: usize, texels: &mut [u32]) -> Result<(), &'static str> {
+    use texture2ddecoder::*;
+    use wgpu::TextureFormat::*;
+    match format {
+        Bc1RgbaUnorm | Bc1RgbaUnormSrgb => decode_bc1a(data, width, height, texels),
+        Bc2RgbaUnorm | Bc2RgbaUnormSrgb => decode_bc2(data, width, height, texels),
+        Bc3RgbaUnorm | Bc3RgbaUnormSrgb => decode_bc3(data, width, height, texels),
+        Bc4RUnorm => decode_bc4(data, width, height, texels),
+        Bc5RgUnorm => decode_bc5(data, width, height, texels),
+        Bc6hRgbUfloat => decode_bc6_unsigned(data, width, height, texels),
+        Bc6hRgbFloat => decode_bc6_signed(data, width, height, texels),
+        Bc7RgbaUnorm | Bc7RgbaUnormSrgb => decode_bc7(data, width, height, texels),
+        Etc2Rgb8Unorm | Etc2Rgb8UnormSrgb => decode_etc2_rgb(data, width, height, texels),
+        Etc2Rgb8A1Unorm | Etc2Rgb8A1UnormSrgb => decode_etc2_rgba1(data, width, height, texels),
+        Etc2Rgba8Unorm | Etc2Rgba8UnormSrgb => decode_etc2_rgba8(data, width, height, texels),
+        EacR11Unorm => decode_eacr(data, width, height, texels),
+        EacR11Snorm => decode_eacr_signed(data, width, height, texels),
+        EacRg11Unorm => decode_eacrg(data, width, height, texels),
+        EacRg11Snorm => decode_eacrg_signed(data, width, height, texels),
+        Astc { .. } => {
+            let (block_width, block_height) = format.block_dimensions();
+            decode_astc(data, width, height, block_width as usize, block_height as usize, texels)
+        },
+        _ => Err("no CPU decoder for this format"),
+    }
+}
+
+// texture2ddecoder reads BC4 and BC5 as unsigned only. The signed blocks are
+// laid out the same with i8 endpoints, so they're decoded here, straight to
+// Rgba8Snorm bytes
+fn decode_signed(format: wgpu::TextureFormat, data: &[u8], width: u32, height: u32) -> Vec<u8> {
+    let channels = if format == wgpu::TextureFormat::Bc5RgSnorm { 2 } else { 1 };
+    let blocks_wide = width.div_ceil(4) as usize;
+    let mut rgba = vec![0u8; (width * height * 4) as usize];
+    for (index, block) in data.chunks_exact(8 * channels).enumerate() {
+        let (block_x, block_y) = (index % blocks_wide * 4, index / blocks_wide * 4);
+        for (channel, half) in block.chunks_exact(8).enumerate() {
+            for (texel, value) in decode_signed_channel(half).into_iter().enumerate() {
+                let (x, y) = (block_x + texel % 4, block_y + texel / 4);
+                if x < width as usize && y < height as usize {
+                    rgba[(y * width as usize + x) * 4 + channel] = value as u8;
+                }
+            }
+        }
+    }
+    // Blue stays 0 and alpha is 1
+    for texel in rgba.chunks_exact_mut(4) {
+        texel[3] = i8::MAX as u8;
+    }
+    rgba
+}
+
+// One 4x4 block of a signed BC4 channel, row by row
+fn decode_signed_channel(block: &[u8]) -> [i8; 16] {
+    // -128 is read as -127 so that both ends stay symmetric
+    let (a, b) = ((block[0] as i8).max(-127) as f32, (block[1] as i8).max(-127) as f32);
+    let palette = if a > b {
+        [a, b, (6.0 * a + b) / 7.0, (5.0 * a + 2.0 * b) / 7.0, (4.0 * a + 3.0 * b) / 7.0,
+            (3.0 * a + 4.0 * b) / 7.0, (2.0 * a + 5.0 * b) / 7.0, (a + 6.0 * b) / 7.0]
+    } else {
+        [a, b, (4.0 * a + b) / 5.0, (3.0 * a + 2.0 * b) / 5.0, (2.0 * a + 3.0 * b) / 5.0,
+            (a + 4.0 * b) / 5.0, -127.0, 127.0]
+    };
+    let indices = u64::from_le_bytes(block[..8].try_into().unwrap()) >> 16;
+    std::array::from_fn(|texel| palette[(indices >> (3 * texel) & 7) as usize].round() as i8)
+}
+
+fn ktx2_format(format: ktx2::Format) -> Result<wgpu::TextureFormat> {
+    use ktx2::Format as K;
+    use wgpu::TextureFormat as W;
+    let format = match format {
+        K::R8G8B8A8_UNORM => W::Rgba8Unorm,
+        K::R8G8B8A8_SRGB => W::Rgba8UnormSrgb,
+        K::B8G8R8A8_UNORM => W::Bgra8Unorm,
+        K::B8G8R8A8_SRGB => W::Bgra8UnormSrgb,
+        K::R16G16B16A16_SFLOAT => W::Rgba16Float,
+        K::BC1_RGB_UNORM_BLOCK | K::BC1_RGBA_UNORM_BLOCK => W::Bc1RgbaUnorm,
+        K::BC1_RGB_SRGB_BLOCK | K::BC1_RGBA_SRGB_BLOCK => W::Bc1RgbaUnormSrgb,
+        K::BC2_UNORM_BLOCK => W::Bc2RgbaUnorm,
+        K::BC2_SRGB_BLOCK => W::Bc2RgbaUnormSrgb,
+        K::BC3_UNORM_BLOCK => W::Bc3RgbaUnorm,
+        K::BC3_SRGB_BLOCK => W::Bc3RgbaUnormSrgb,
+        K::BC4_UNORM_BLOCK => W::Bc4RUnorm,
+        K::BC4_SNORM_BLOCK => W::Bc4RSnorm,
+        K::BC5_UNORM_BLOCK => W::Bc5RgUnorm,
+        K::BC5_SNORM_BLOCK => W::Bc5RgSnorm,
+        K::BC6H_UFLOAT_BLOCK => W::Bc6hRgbUfloat,
+        K::BC6H_SFLOAT_BLOCK => W::Bc6hRgbFloat,
+        K::BC7_UNORM_BLOCK => W::Bc7RgbaUnorm,
+        K::BC7_SRGB_BLOCK => W::Bc7RgbaUnormSrgb,
+        K::ETC2_R8G8B8_UNORM_BLOCK => W::Etc2Rgb8Unorm,
+        K::ETC2_R8G8B8_SRGB_BLOCK => W::Etc2Rgb8UnormSrgb,
+        K::ETC2_R8G8B8A1_UNORM_BLOCK => W::Etc2Rgb8A1Unorm,
+        K::ETC2_R8G8B8A1_SRGB_BLOCK => W::Etc2Rgb8A1UnormSrgb,
+        K::ETC2_R8G8B8A8_UNORM_BLOCK => W::Etc2Rgba8Unorm,
+        K::ETC2_R8G8B8A8_SRGB_BLOCK => W::Etc2Rgba8UnormSrgb,
+        K::EAC_R11_UNORM_BLOCK => W::EacR11Unorm,
+        K::EAC_R11_SNORM_BLOCK => W::EacR11Snorm,
+        K::EAC_R11G11_UNORM_BLOCK => W::EacRg11Unorm,
+        K::EAC_R11G11_SNORM_BLOCK => W::EacRg11Snorm,
+        // The LDR ASTC formats come in unorm/sRGB pairs, one pair per block
+        // size, in the same order as wgpu's AstcBlock
+        format if (K::ASTC_4x4_UNORM_BLOCK..=K::ASTC_12x12_SRGB_BLOCK).contains(&format) => {
+            use wgpu::AstcBlock::*;
+            let index = format.0.get() - K::ASTC_4x4_UNORM_BLOCK.0.get();
+            let blocks = [B4x4, B5x4, B5x5, B6x5, B6x6, B8x5, B8x6, B8x8, B10x5, B10x6, B10x8, B10x10, B12x10, B12x12];
+            W::Astc {
+                block: blocks[index as usize / 2],
+                channel: if index.is_multiple_of(2) { wgpu::AstcChannel::Unorm } else { wgpu::AstcChannel::UnormSrgb },
+            }
+        },
+        format => bail!("Unsupported KTX2 format {:?}", format),
+    };
+    Ok(format)
+}
+
+fn dxgi_format(format: ddsfile::DxgiFormat) -> Result<wgpu::TextureFormat> {
+    use ddsfile::DxgiFormat as D;
+    use wgpu::TextureFormat as W;
+    let format = match format {
+        D::R8G8B8A8_UNorm => W::Rgba8Unorm,
+        D::R8G8B8A8_UNorm_sRGB => W::Rgba8UnormSrgb,
+        D::B8G8R8A8_UNorm => W::Bgra8Unorm,
+        D::B8G8R8A8_UNorm_sRGB => W::Bgra8UnormSrgb,
+        D::R16G16B16A16_Float => W::Rgba16Float,
+        D::BC1_UNorm => W::Bc1RgbaUnorm,
+        D::BC1_UNorm_sRGB => W::Bc1RgbaUnormSrgb,
+        D::BC2_UNorm => W::Bc2RgbaUnorm,
+        D::BC2_UNorm_sRGB => W::Bc2RgbaUnormSrgb,
+        D::BC3_UNorm => W::Bc3RgbaUnorm,
+        D::BC3_UNorm_sRGB => W::Bc3RgbaUnormSrgb,
+        D::BC4_UNorm => W::Bc4RUnorm,
+        D::BC4_SNorm => W::Bc4RSnorm,
+        D::BC5_UNorm => W::Bc5RgUnorm,
+        D::BC5_SNorm => W::Bc5RgSnorm,
+        D::BC6H_UF16 => W::Bc6hRgbUfloat,
+        D::BC6H_SF16 => W::Bc6hRgbFloat,
+        D::BC7_UNorm => W::Bc7RgbaUnorm,
+        D::BC7_UNorm_sRGB => W::Bc7RgbaUnormSrgb,
+        format => bail!("Unsupported DDS format {:?}", format),
+    };
+    Ok(format)
+}
+
+fn legacy_dds_format(dds: &ddsfile::Dds, color_space: ColorSpace) -> Result<wgpu::TextureFormat> {
+    use wgpu::TextureFormat as W;
+    let srgb = color_space == ColorSpace::Srgb;
+    let pick = |linear: W, srgb_format: W| if srgb { srgb_format } else { linear };
+
+    // FourCCs for BC4 and BC5 that ddsfile has no D3DFormat for
+    match dds.header.spf.fourcc.as_ref().map(|fourcc| fourcc.0) {
+        Some(ddsfile::FourCC::BC4_UNORM) | Some(ddsfile::FourCC::ATI1) => return Ok(W::Bc4RUnorm),
+        Some(ddsfile::FourCC::BC4_SNORM) => return Ok(W::Bc4RSnorm),
+        Some(ddsfile::FourCC::BC5_UNORM) => return Ok(W::Bc5RgUnorm),
+        Some(ddsfile::FourCC::BC5_SNORM) => return Ok(W::Bc5RgSnorm),
+        _ => {},
+    }
+
+    use ddsfile::D3DFormat as D;
+    let format = match dds.get_d3d_format() {
+        Some(D::DXT1) => pick(W::Bc1RgbaUnorm, W::Bc1RgbaUnormSrgb),
+        // DXT2 and DXT4 are the premultiplied variants, stored the same way
+        Some(D::DXT2 | D::DXT3) => pick(W::Bc2RgbaUnorm, W::Bc2RgbaUnormSrgb),
+        Some(D::DXT4 | D::DXT5) => pick(W::Bc3RgbaUnorm, W::Bc3RgbaUnormSrgb),
+        // D3D names channels from the most significant bit down, so these
+        // are the reverse of their byte order
+        Some(D::A8B8G8R8) => pick(W::Rgba8Unorm, W::Rgba8UnormSrgb),
+        Some(D::A8R8G8B8) => pick(W::Bgra8Unorm, W::Bgra8UnormSrgb),
+        Some(D::A16B16G16R16F) => W::Rgba16Float,
+        format => bail!("Unsupported DDS format {:?}", format),
+    };
+    Ok(format)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Endpoints then sixteen 3-bit indices
+    fn signed_block(a: i8, b: i8, indices: [u64; 16]) -> [u8; 8] {
+        let bits = indices.iter().enumerate().fold(0u64, |bits, (texel, index)| bits | index << (3 * texel));
+        let mut block = (bits << 16).to_le_bytes();
+        block[0] = a as u8;
+        block[1] = b as u8;
+        block
+    }
+
+    #[test]
+    fn signed_bc4_interpolates_between_signed_endpoints() {
+        let block = signed_block(100, -100, std::array::from_fn(|texel| texel as u64 % 8));
+        let texels = decode_signed_channel(&block);
+        assert_eq!(&texels[..8], &[100, -100, 71, 43, 14, -14, -43, -71]);
+
+        // With the endpoints the other way round 6 and 7 are the extremes
+        let block = signed_block(-100, 100, std::array::from_fn(|texel| texel as u64 % 8));
+        assert_eq!(&decode_signed_channel(&block)[..8], &[-100, 100, -60, -20, 20, 60, -127, 127]);
+    }
+
+    #[test]
+    fn signed_bc5_fills_red_and_green() {
+        let mut data = signed_block(-50, 0, [0; 16]).to_vec();
+        data.extend(signed_block(0, 50, [1; 16]));
+        let rgba = decode_signed(wgpu::TextureFormat::Bc5RgSnorm, &data, 2, 2);
+        assert_eq!(rgba.len(), 2 * 2 * 4);
+        assert!(rgba.chunks_exact(4).all(|texel| texel == [-50i8 as u8, 50, 0, 127]));
+    }
+
+    #[test]
+    fn cube_arrays_are_rejected() {
+        let image = CompressedImage {
+            format: wgpu::TextureFormat::Rgba8Unorm,
+            width: 4,
+            height: 4,
+            layers: 12,
+            cube: true,
+            levels: vec![vec![0; 4 * 4 * 4 * 12]],
+        };
+        assert!(image.validate().is_err());
+        assert!(CompressedImage { layers: 6, ..image }.validate().is_ok());
+    }
+}
@@ -0,0 +1,14 @@
+// Shared cubemap helpers, prepended to the shaders that need them
+
+// Direction through the point at `uv` in -1..1 on cube face `index`, with v
+// pointing down, following the usual +X, -X, +Y, -Y, +Z, -Z face layout
+fn face_direction(index: u32, uv: vec2<f32>) -> vec3<f32> {
+    switch index {
+        case 0u: { return vec3<f32>(1.0, -uv.y, -uv.x); }
+        case 1u: { return vec3<f32>(-1.0, -uv.y, uv.x); }
+        case 2u: { return vec3<f32>(uv.x, 1.0, uv.y); }
+        case 3u: { return vec3<f32>(uv.x, -1.0, -uv.y); }
+        case 4u: { return vec3<f32>(uv.x, -uv.y, 1.0); }
+        default: { return vec3<f32>(-uv.x, -uv.y, -1.0); }
+    }
+}
@@ -0,0 +1,170 @@
+use wgpu::util::DeviceExt;
+
+use crate::{
+    sampler::{SamplerCache, SamplerOptions},
+    texture::Texture,
+};
+
+pub const CUBE_FACES: u32 = 6;
+
+// Must match equirect.wgsl
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct FaceUniform {
+    index: u32,
+    size: f32,
+    _padding: [u32; 2],
+}
+
+// Renders `equirect` onto level 0 of all six layers of `cube`, which needs
+// RENDER_ATTACHMENT usage and a renderable format
+pub fn project_equirect(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    equirect: &Texture,
+    cube: &wgpu::Texture,
+    samplers: &SamplerCache,
+) {
+    let face_size = cube.width();
+    let stride = wgpu::util::align_to(
+        std::mem::size_of::<FaceUniform>() as wgpu::BufferAddress,
+        device.limits().min_uniform_buffer_offset_alignment as wgpu::BufferAddress,
+    );
+    let mut faces = vec![0u8; (stride * CUBE_FACES as wgpu::BufferAddress) as usize];
+    for index in 0..CUBE_FACES {
+        let offset = (index as wgpu::BufferAddress * stride) as usize;
+        let uniform = FaceUniform { index, size: face_size as f32, _padding: [0; 2] };
+        faces[offset..offset + std::mem::size_of::<FaceUniform>()].copy_from_slice(bytemuck::bytes_of(&uniform));
+    }
+    let face_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("Equirect Face Buffer"),
+        contents: &faces,
+        usage: wgpu::BufferUsages::UNIFORM,
+    });
+
+    // Wraps around horizontally so the seam at the back filters cleanly.
+    // Faces smaller than the panorama need it filtered when minified too
+    let sampler = samplers.get(device, &SamplerOptions {
+        address_mode_u: wgpu::AddressMode::Repeat,
+        min_filter: wgpu::FilterMode::Linear,
+        ..Default::default()
+    });
+
+    let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        entries: &[
+            wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    multisampled: false,
+                },
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 1,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 2,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: true,
+                    min_binding_size: wgpu::BufferSize::new(std::mem::size_of::<FaceUniform>() as u64),
+                },
+                count: None,
+            },
+        ],
+        label: Some("equirect_bind_group_layout"),
+    });
+    let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+        layout: &bind_group_layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(&equirect.view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::Sampler(&sampler),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                    buffer: &face_buffer,
+                    offset: 0,
+                    size: wgpu::BufferSize::new(std::mem::size_of::<FaceUniform>() as u64),
+                }),
+            },
+        ],
+        label: Some("equirect_bind_group"),
+    });
+
+    let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some("Equirect Shader"),
+        source: wgpu::ShaderSource::Wgsl(concat!(include_str!("cube.wgsl"), include_str!("equirect.wgsl")).into()),
+    });
+    let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: Some("Equirect Pipeline Layout"),
+        bind_group_layouts: &[&bind_group_layout],
+        push_constant_ranges: &[],
+    });
+    let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Equirect Pipeline"),
+        layout: Some(&pipeline_layout),
+        vertex: wgpu::VertexState {
+            module: &shader,
+            entry_point: "vs_main",
+            buffers: &[],
+            compilation_options: wgpu::PipelineCompilationOptions::default(),
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: &shader,
+            entry_point: "fs_main",
+            targets: &[Some(cube.format().into())],
+            compilation_options: wgpu::PipelineCompilationOptions::default(),
+        }),
+        primitive: wgpu::PrimitiveState::default(),
+        depth_stencil: None,
+        multisample: wgpu::MultisampleState::default(),
+        multiview: None,
+        cache: None,
+    });
+
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+        label: Some("Equirect Encoder"),
+    });
+    for face in 0..CUBE_FACES {
+        let target = cube.create_view(&wgpu::TextureViewDescriptor {
+            label: Some("cube_face_view"),
+            dimension: Some(wgpu::TextureViewDimension::D2),
+            base_mip_level: 0,
+            mip_level_count: Some(1),
+            base_array_layer: face,
+            array_layer_count: Some(1),
+            ..Default::default()
+        });
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Equirect Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: &target,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+        pass.set_pipeline(&pipeline);
+        pass.set_bind_group(0, &bind_group, &[(face as wgpu::BufferAddress * stride) as wgpu::DynamicOffset]);
+        pass.draw(0..3, 0..1);
+    }
+    queue.submit(std::iter::once(encoder.finish()));
+}
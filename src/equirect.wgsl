@@ -0,0 +1,36 @@
+// Renders one cube face by looking up each texel's direction in a
+// latitude/longitude panorama
+@group(0) @binding(0) var equirect_texture: texture_2d<f32>;
+@group(0) @binding(1) var equirect_sampler: sampler;
+
+struct Face {
+    index: u32,
+    size: f32,
+    _padding: vec2<u32>,
+}
+@group(0) @binding(2) var<uniform> face: Face;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+}
+
+// Fullscreen triangle
+@vertex
+fn vs_main(@builtin(vertex_index) in_vertex_index: u32) -> VertexOutput {
+    let uv = vec2<f32>(f32((in_vertex_index << 1u) & 2u), f32(in_vertex_index & 2u));
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(uv * 2.0 - 1.0, 0.0, 1.0);
+    return out;
+}
+
+const PI: f32 = 3.14159265358979;
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let uv = in.clip_position.xy / face.size * 2.0 - 1.0;
+    let direction = normalize(face_direction(face.index, uv));
+    let longitude = atan2(direction.z, direction.x);
+    let latitude = asin(clamp(direction.y, -1.0, 1.0));
+    let equirect_uv = vec2<f32>(longitude / (2.0 * PI) + 0.5, 0.5 - latitude / PI);
+    return textureSampleLevel(equirect_texture, equirect_sampler, equirect_uv, 0.0);
+}
@@ -0,0 +1,160 @@
+use cgmath::{EuclideanSpace, InnerSpace, Matrix};
+
+use crate::{bounds::{Aabb, Sphere}, camera::Camera};
+
+// Points with `normal . p + distance >= 0` are on the inside. Planes that
+// don't exist (the far plane of an infinite projection) let everything through
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Plane {
+    pub normal: cgmath::Vector3<f32>,
+    pub distance: f32,
+}
+
+impl Plane {
+    fn from_coefficients(v: cgmath::Vector4<f32>) -> Self {
+        let normal = v.truncate();
+        let length = normal.magnitude();
+        if length <= f32::EPSILON {
+            return Self {
+                normal: cgmath::Vector3::new(0.0, 0.0, 0.0),
+                distance: f32::MAX,
+            };
+        }
+        Self {
+            normal: normal / length,
+            distance: v.w / length,
+        }
+    }
+
+    pub fn signed_distance(&self, point: cgmath::Point3<f32>) -> f32 {
+        self.normal.dot(point.to_vec()) + self.distance
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Frustum {
+    pub planes: [Plane; 6],
+}
+
+impl Frustum {
+    // Gribb/Hartmann plane extraction for wgpu clip space, where depth runs
+    // from 0 to w rather than OpenGL's -w to w
+    pub fn from_matrix(m: cgmath::Matrix4<f32>) -> Self {
+        let (r0, r1, r2, r3) = (m.row(0), m.row(1), m.row(2), m.row(3));
+        Self {
+            planes: [
+                Plane::from_coefficients(r3 + r0),
+                Plane::from_coefficients(r3 - r0),
+                Plane::from_coefficients(r3 + r1),
+                Plane::from_coefficients(r3 - r1),
+                Plane::from_coefficients(r2),
+                Plane::from_coefficients(r3 - r2),
+            ],
+        }
+    }
+
+    pub fn from_camera(camera: &Camera) -> Self {
+        Self::from_matrix(camera.build_view_projection_matrix())
+    }
+
+    pub fn intersects_sphere(&self, sphere: &Sphere) -> bool {
+        self.planes.iter().all(|plane| plane.signed_distance(sphere.center) >= -sphere.radius)
+    }
+
+    // Only tests the box corner furthest along each plane normal, so boxes
+    // near frustum corners can be kept even though they're just outside
+    pub fn intersects_aabb(&self, aabb: &Aabb) -> bool {
+        self.planes.iter().all(|plane| {
+            let furthest = cgmath::Point3::new(
+                if plane.normal.x >= 0.0 { aabb.max.x } else { aabb.min.x },
+                if plane.normal.y >= 0.0 { aabb.max.y } else { aabb.min.y },
+                if plane.normal.z >= 0.0 { aabb.max.z } else { aabb.min.z },
+            );
+            plane.signed_distance(furthest) >= 0.0
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::camera::{DepthMode, OrthographicSize, Projection};
+
+    // Looking down -Z from the origin, 20 units across at distance 10 for
+    // both projections
+    fn camera(projection: Projection, depth_mode: DepthMode) -> Camera {
+        Camera {
+            eye: cgmath::Point3::new(0.0, 0.0, 0.0),
+            target: cgmath::Point3::new(0.0, 0.0, -1.0),
+            up: cgmath::Vector3::unit_y(),
+            aspect: 1.0,
+            fovy: 90.0,
+            znear: 1.0,
+            zfar: 100.0,
+            projection,
+            depth_mode,
+        }
+    }
+
+    fn cube(center: [f32; 3], half_size: f32) -> Aabb {
+        let center = cgmath::Point3::from(center);
+        let half = cgmath::Vector3::new(half_size, half_size, half_size);
+        Aabb {
+            min: center - half,
+            max: center + half,
+        }
+    }
+
+    fn cameras() -> Vec<Camera> {
+        let projections = [Projection::Perspective, Projection::Orthographic(OrthographicSize::Height(20.0))];
+        let depth_modes = [DepthMode::Standard, DepthMode::ReverseZ];
+        projections
+            .iter()
+            .flat_map(|projection| depth_modes.iter().map(|depth_mode| camera(*projection, *depth_mode)))
+            .collect()
+    }
+
+    #[test]
+    fn boxes_inside_outside_and_straddling() {
+        for camera in cameras() {
+            let frustum = Frustum::from_camera(&camera);
+            let check = |aabb: Aabb, expected: bool, what: &str| {
+                assert_eq!(frustum.intersects_aabb(&aabb), expected, "box {} with {:?} {:?}", what, camera.projection, camera.depth_mode);
+                let sphere = aabb.bounding_sphere();
+                assert_eq!(frustum.intersects_sphere(&sphere), expected, "sphere {} with {:?} {:?}", what, camera.projection, camera.depth_mode);
+            };
+            check(cube([0.0, 0.0, -10.0], 1.0), true, "in front");
+            check(cube([2.0, -3.0, -50.0], 1.0), true, "off centre");
+            check(cube([10.0, 0.0, -10.0], 1.0), true, "across the right side");
+            check(cube([0.0, 0.0, -1.0], 0.5), true, "across the near plane");
+            check(cube([0.0, 0.0, 10.0], 1.0), false, "behind");
+            check(cube([30.0, 0.0, -10.0], 1.0), false, "to the right");
+            check(cube([0.0, -30.0, -10.0], 1.0), false, "below");
+            check(cube([0.0, 0.0, -0.5], 0.25), false, "before the near plane");
+        }
+    }
+
+    #[test]
+    fn far_plane_depends_on_projection() {
+        let far = cube([0.0, 0.0, -150.0], 1.0);
+        let across_far = cube([0.0, 0.0, -100.0], 1.0);
+        for camera in cameras() {
+            let frustum = Frustum::from_camera(&camera);
+            assert!(frustum.intersects_aabb(&across_far), "{:?} {:?}", camera.projection, camera.depth_mode);
+            // Only reverse-Z perspective drops the far plane
+            let infinite = camera.projection == Projection::Perspective && camera.depth_mode == DepthMode::ReverseZ;
+            assert_eq!(frustum.intersects_aabb(&far), infinite, "{:?} {:?}", camera.projection, camera.depth_mode);
+        }
+    }
+
+    #[test]
+    fn planes_point_inwards() {
+        for camera in cameras() {
+            let frustum = Frustum::from_camera(&camera);
+            let inside = cgmath::Point3::new(0.0, 0.0, -10.0);
+            for plane in &frustum.planes {
+                assert!(plane.signed_distance(inside) > 0.0, "{:?} with {:?} {:?}", plane, camera.projection, camera.depth_mode);
+            }
+        }
+    }
+}
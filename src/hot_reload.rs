@@ -0,0 +1,117 @@
+// Dev mode asset watching, behind the hot-reload feature. Shaders and images
+// are normally baked in or loaded once; with this they're picked up from
+// src/ and the directories textures were loaded from whenever they're saved
+
+use std::{
+    collections::HashSet,
+    future::Future,
+    path::{Path, PathBuf},
+    sync::mpsc,
+    task::{self, Poll, Waker},
+};
+
+use anyhow::*;
+use notify::Watcher;
+
+#[cfg(target_arch = "wasm32")]
+compile_error!("hot-reload watches files on disk, so it's native only");
+
+// Where the include_str!s read from, so edits land where they'd be compiled
+pub const SOURCE_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/src");
+
+pub struct AssetWatcher {
+    // Stops watching when dropped
+    watcher: notify::RecommendedWatcher,
+    events: mpsc::Receiver<notify::Result<notify::Event>>,
+    // With whether their subdirectories are watched too
+    directories: Vec<(PathBuf, bool)>,
+}
+
+impl AssetWatcher {
+    pub fn new() -> Result<Self> {
+        let (sender, events) = mpsc::channel();
+        let watcher = notify::recommended_watcher(sender)
+            .context("Couldn't start watching for asset changes")?;
+        Ok(Self {
+            watcher,
+            events,
+            directories: Vec::new(),
+        })
+    }
+
+    // Does nothing for a directory that's already covered, so each change is
+    // only reported by one watch
+    pub fn watch(&mut self, directory: &Path, recursive: bool) -> Result<()> {
+        let directory = directory
+            .canonicalize()
+            .with_context(|| format!("Couldn't watch {}", directory.display()))?;
+        let covered = self.directories.iter().any(|(watched, watched_recursive)| {
+            *watched == directory || (*watched_recursive && directory.starts_with(watched))
+        });
+        if covered {
+            return Ok(());
+        }
+        let mode = if recursive { notify::RecursiveMode::Recursive } else { notify::RecursiveMode::NonRecursive };
+        self.watcher
+            .watch(&directory, mode)
+            .with_context(|| format!("Couldn't watch {}", directory.display()))?;
+        log::info!("Watching {} for changes", directory.display());
+        self.directories.push((directory, recursive));
+        Ok(())
+    }
+
+    // Files written or replaced since the last call, each once however many
+    // events it raised. Editors that save by renaming show up as creations
+    pub fn changed(&self) -> Vec<PathBuf> {
+        let mut changed = HashSet::new();
+        for event in self.events.try_iter() {
+            match event {
+                Result::Ok(event) if event.kind.is_modify() || event.kind.is_create() => {
+                    changed.extend(event.paths.iter().map(|path| path.canonicalize().unwrap_or_else(|_| path.clone())));
+                },
+                Result::Ok(_) => {},
+                Err(e) => log::error!("Asset watcher: {}", e),
+            }
+        }
+        changed.into_iter().collect()
+    }
+}
+
+pub fn is_any_of(path: &Path, files: &[&str]) -> bool {
+    files.iter().any(|file| Path::new(SOURCE_DIR).join(file).canonicalize().is_ok_and(|source| source == path))
+}
+
+// `files` from SOURCE_DIR joined in order, the way the shaders are built with
+// concat!, and checked with naga so mistakes are reported with their line
+// rather than failing inside wgpu
+pub fn read_shader(files: &[&str]) -> Result<String> {
+    let mut source = String::new();
+    for file in files {
+        let path = Path::new(SOURCE_DIR).join(file);
+        source += &std::fs::read_to_string(&path).with_context(|| format!("Couldn't read {}", path.display()))?;
+    }
+    // Line numbers count through the joined source
+    let name = files.join(" + ");
+    let module = naga::front::wgsl::parse_str(&source)
+        .map_err(|e| anyhow!("{}", e.emit_to_string_with_path(&source, &name)))?;
+    // Which capabilities the device has is checked by wgpu, in `checked`
+    naga::valid::Validator::new(naga::valid::ValidationFlags::all(), naga::valid::Capabilities::all())
+        .validate(&module)
+        .map_err(|e| anyhow!("{}", e.emit_to_string_with_path(&source, &name)))?;
+    Ok(source)
+}
+
+// Runs `create` in a validation error scope, so a mistake naga can't see
+// alone, such as a binding missing from the pipeline layout, is returned
+// instead of hitting the device's uncaptured error handler
+pub fn checked<T>(device: &wgpu::Device, create: impl FnOnce() -> T) -> Result<T> {
+    device.push_error_scope(wgpu::ErrorFilter::Validation);
+    let value = create();
+    // Native error scopes are resolved by the time they're popped
+    let mut error = std::pin::pin!(device.pop_error_scope());
+    match error.as_mut().poll(&mut task::Context::from_waker(Waker::noop())) {
+        Poll::Ready(None) => Ok(value),
+        Poll::Ready(Some(e)) => Err(anyhow!("{}", e)),
+        Poll::Pending => bail!("Validation result wasn't ready"),
+    }
+}
@@ -4,6 +4,7 @@ use winit::{
     keyboard::{KeyCode, PhysicalKey},
     window::WindowBuilder,
 };
+use web_time::Instant;
 #[cfg(target_arch="wasm32")]
 use wasm_bindgen::prelude::*;
 #[cfg(target_arch="wasm32")]
@@ -14,9 +15,30 @@ use console_error_panic_hook;
 use wgpu::web_sys;
 
 
+mod arcball;
+mod atlas;
+mod bookmarks;
+mod bounds;
 mod camera;
+mod camera_path;
+mod color;
+mod compressed;
+mod cubemap;
+mod frustum;
+#[cfg(feature = "hot-reload")]
+mod hot_reload;
+mod mipmap;
+mod picking;
+mod procedural;
+mod sampler;
+mod screenshot;
+mod skybox;
 mod state;
+#[cfg(test)]
+mod testing;
 mod texture;
+mod texture_manager;
+mod viewport;
 
 use state::State;
 
@@ -50,13 +72,20 @@ pub async fn run() {
             .expect("Couldn't append canvas to document body.");
     }
 
-    let mut state = State::new(&window).await;
+    let mut state = match State::new(&window).await {
+        Ok(state) => state,
+        Err(e) => {
+            log::error!("{:#}", e);
+            return;
+        },
+    };
+    let mut last_render_time = Instant::now();
 
     let _ = event_loop.run(move |event, control_flow| match event {
         Event::WindowEvent {
             ref event,
             window_id,
-        } if window_id == state.window().id() => if !state.input(event) {
+        } if window_id == state.window().id() && !state.input(event) => {
             match event {
                 WindowEvent::CloseRequested
                 | WindowEvent::KeyboardInput {
@@ -73,7 +102,10 @@ pub async fn run() {
                 WindowEvent::RedrawRequested => {
                     state.window().request_redraw();
 
-                    state.update();
+                    let now = Instant::now();
+                    let dt = now - last_render_time;
+                    last_render_time = now;
+                    state.update(dt);
 
                     match state.render() {
                         Ok(_) =>  {},
@@ -92,6 +124,12 @@ pub async fn run() {
                 _ => {},
             }
         },
+        Event::DeviceEvent {
+            ref event,
+            ..
+        } => {
+            state.device_input(event);
+        },
         _ => {},
     });
 }
@@ -1,7 +1,5 @@
 use learnwgpu::run;
 
-use tokio;
-
 use std::error::Error;
 
 #[tokio::main(flavor="current_thread")]
@@ -0,0 +1,351 @@
+// Mip chain generation. The GPU path renders each level from the one above
+// it with mipmap.wgsl; the CPU path applies the same filter so the two can be
+// compared texel for texel
+
+use crate::{color, cubemap::CUBE_FACES};
+
+pub fn mip_level_count(width: u32, height: u32) -> u32 {
+    32 - width.max(height).max(1).leading_zeros()
+}
+
+pub fn mip_size(size: u32, level: u32) -> u32 {
+    (size >> level).max(1)
+}
+
+// Fills levels 1.. of every layer of `texture` from level 0. The texture
+// needs RENDER_ATTACHMENT and TEXTURE_BINDING usage and a renderable format.
+// Six square layers are treated as a cubemap
+pub fn generate(device: &wgpu::Device, queue: &wgpu::Queue, texture: &wgpu::Texture) {
+    let mip_level_count = texture.mip_level_count();
+    if mip_level_count < 2 {
+        return;
+    }
+    let layers = texture.depth_or_array_layers();
+    let cube = layers == CUBE_FACES && texture.width() == texture.height();
+    let array = layers > 1 && !cube;
+
+    let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some("Mipmap Shader"),
+        source: wgpu::ShaderSource::Wgsl(concat!(include_str!("cube.wgsl"), include_str!("mipmap.wgsl")).into()),
+    });
+    let source_entries: &[wgpu::BindGroupLayoutEntry] = if cube {
+        &[
+            wgpu::BindGroupLayoutEntry {
+                binding: 1,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                    view_dimension: wgpu::TextureViewDimension::Cube,
+                    multisampled: false,
+                },
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 2,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::NonFiltering),
+                count: None,
+            },
+        ]
+    } else if array {
+        &[
+            wgpu::BindGroupLayoutEntry {
+                binding: 3,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                    view_dimension: wgpu::TextureViewDimension::D2Array,
+                    multisampled: false,
+                },
+                count: None,
+            },
+        ]
+    } else {
+        &[
+            wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    multisampled: false,
+                },
+                count: None,
+            },
+        ]
+    };
+    let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        entries: source_entries,
+        label: Some("mipmap_bind_group_layout"),
+    });
+    let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: Some("Mipmap Pipeline Layout"),
+        bind_group_layouts: &[&bind_group_layout],
+        push_constant_ranges: &[],
+    });
+    let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Mipmap Pipeline"),
+        layout: Some(&pipeline_layout),
+        vertex: wgpu::VertexState {
+            module: &shader,
+            entry_point: "vs_main",
+            buffers: &[],
+            compilation_options: wgpu::PipelineCompilationOptions::default(),
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: &shader,
+            entry_point: if cube { "fs_cube" } else if array { "fs_array" } else { "fs_main" },
+            targets: &[Some(texture.format().into())],
+            compilation_options: wgpu::PipelineCompilationOptions::default(),
+        }),
+        primitive: wgpu::PrimitiveState::default(),
+        depth_stencil: None,
+        multisample: wgpu::MultisampleState::default(),
+        multiview: None,
+        cache: None,
+    });
+    let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+        label: Some("mipmap_sampler"),
+        ..Default::default()
+    });
+
+    let view = |dimension, level, layer, layer_count| texture.create_view(&wgpu::TextureViewDescriptor {
+        label: Some("mip_level_view"),
+        dimension: Some(dimension),
+        base_mip_level: level,
+        mip_level_count: Some(1),
+        base_array_layer: layer,
+        array_layer_count: Some(layer_count),
+        ..Default::default()
+    });
+    // Each view covers every layer, so one bind group does per level
+    let bind_group = |level| {
+        let (source, binding) = if cube {
+            (view(wgpu::TextureViewDimension::Cube, level, 0, CUBE_FACES), 1)
+        } else if array {
+            (view(wgpu::TextureViewDimension::D2Array, level, 0, layers), 3)
+        } else {
+            (view(wgpu::TextureViewDimension::D2, level, 0, 1), 0)
+        };
+        let mut entries = vec![
+            wgpu::BindGroupEntry {
+                binding,
+                resource: wgpu::BindingResource::TextureView(&source),
+            },
+        ];
+        if cube {
+            entries.push(wgpu::BindGroupEntry {
+                binding: 2,
+                resource: wgpu::BindingResource::Sampler(&sampler),
+            });
+        }
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &bind_group_layout,
+            entries: &entries,
+            label: Some("mipmap_bind_group"),
+        })
+    };
+
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+        label: Some("Mipmap Encoder"),
+    });
+    for level in 1..mip_level_count {
+        let source = bind_group(level - 1);
+        for layer in 0..layers {
+            let target = view(wgpu::TextureViewDimension::D2, level, layer, 1);
+
+            let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Mipmap Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &target,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                depth_stencil_attachment: None,
+                occlusion_query_set: None,
+                timestamp_writes: None,
+            });
+            pass.set_pipeline(&pipeline);
+            pass.set_bind_group(0, &source, &[]);
+            pass.draw(0..3, layer..layer + 1);
+        }
+    }
+    queue.submit(std::iter::once(encoder.finish()));
+}
+
+// Every level below `image`, largest first. `srgb` filters in linear space
+// the way sampling an Rgba8UnormSrgb texture does. Each level is rounded to
+// 8 bits before the next is made from it, as happens on the GPU
+pub fn cpu_mip_chain(image: &image::RgbaImage, srgb: bool) -> Vec<image::RgbaImage> {
+    let count = mip_level_count(image.width(), image.height());
+    let mut levels: Vec<image::RgbaImage> = Vec::with_capacity(count as usize - 1);
+    for _ in 1..count {
+        let next = downsample(levels.last().unwrap_or(image), srgb);
+        levels.push(next);
+    }
+    levels
+}
+
+// Same as `cpu_mip_chain` for images that are already linear, such as HDR
+pub fn cpu_mip_chain_linear(image: &image::Rgba32FImage) -> Vec<image::Rgba32FImage> {
+    let count = mip_level_count(image.width(), image.height());
+    let mut levels: Vec<image::Rgba32FImage> = Vec::with_capacity(count as usize - 1);
+    for _ in 1..count {
+        let next = downsample_linear(levels.last().unwrap_or(image));
+        levels.push(next);
+    }
+    levels
+}
+
+pub fn downsample(source: &image::RgbaImage, srgb: bool) -> image::RgbaImage {
+    let decode = |value: u8, channel: usize| {
+        let value = value as f32 / 255.0;
+        if srgb && channel < 3 { color::srgb_to_linear(value) } else { value }
+    };
+    let encode = |value: f32, channel: usize| {
+        let value = if srgb && channel < 3 { color::linear_to_srgb(value) } else { value };
+        (value.clamp(0.0, 1.0) * 255.0).round() as u8
+    };
+
+    let linear = image::Rgba32FImage::from_fn(source.width(), source.height(), |x, y| {
+        let texel = source.get_pixel(x, y);
+        image::Rgba([0, 1, 2, 3].map(|channel| decode(texel[channel], channel)))
+    });
+    let downsampled = downsample_linear(&linear);
+    image::RgbaImage::from_fn(downsampled.width(), downsampled.height(), |x, y| {
+        let texel = downsampled.get_pixel(x, y);
+        image::Rgba([0, 1, 2, 3].map(|channel| encode(texel[channel], channel)))
+    })
+}
+
+pub fn downsample_linear(source: &image::Rgba32FImage) -> image::Rgba32FImage {
+    let (width, height) = (mip_size(source.width(), 1), mip_size(source.height(), 1));
+    image::Rgba32FImage::from_fn(width, height, |x, y| {
+        let (x_first, x_weights) = footprint(x, source.width());
+        let (y_first, y_weights) = footprint(y, source.height());
+        let mut color = [0.0f32; 4];
+        for (j, wy) in y_weights.iter().enumerate() {
+            for (i, wx) in x_weights.iter().enumerate() {
+                let texel = source.get_pixel(x_first + i as u32, y_first + j as u32);
+                for (channel, c) in color.iter_mut().enumerate() {
+                    *c += texel[channel] * wx * wy;
+                }
+            }
+        }
+        image::Rgba(color)
+    })
+}
+
+// Same as `footprint` in mipmap.wgsl
+fn footprint(x: u32, source_size: u32) -> (u32, Vec<f32>) {
+    if source_size == 1 {
+        return (0, vec![1.0]);
+    }
+    if source_size.is_multiple_of(2) {
+        return (2 * x, vec![0.5, 0.5]);
+    }
+    let ratio = source_size as f32 / (source_size / 2) as f32;
+    let xf = x as f32;
+    (2 * x, vec![
+        (2.0 * xf + 1.0 - xf * ratio) / ratio,
+        1.0 / ratio,
+        ((xf + 1.0) * ratio - (2.0 * xf + 2.0)) / ratio,
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    // Noise, so a wrong weight anywhere in the footprint shows up
+    fn test_image(width: u32, height: u32) -> image::RgbaImage {
+        image::RgbaImage::from_fn(width, height, |x, y| {
+            let h = (x.wrapping_mul(73856093) ^ y.wrapping_mul(19349663)).wrapping_mul(2654435761);
+            image::Rgba(h.to_le_bytes())
+        })
+    }
+
+    #[test]
+    fn footprint_weights_sum_to_one() {
+        for source_size in 1..40 {
+            for x in 0..mip_size(source_size, 1) {
+                let (first, weights) = footprint(x, source_size);
+                assert!(first + weights.len() as u32 <= source_size);
+                assert!(weights.iter().all(|w| *w >= 0.0));
+                assert!((weights.iter().sum::<f32>() - 1.0).abs() < 1e-5, "size {} texel {}", source_size, x);
+            }
+        }
+    }
+
+    #[test]
+    fn odd_sizes_weight_by_coverage() {
+        // Five texels into two: each output covers 2.5 of them
+        let source = image::Rgba32FImage::from_fn(5, 1, |x, _| image::Rgba([x as f32; 4]));
+        let level = downsample_linear(&source);
+        assert_eq!(level.dimensions(), (2, 1));
+        assert!((level.get_pixel(0, 0)[0] - (0.0 + 1.0 + 2.0 * 0.5) / 2.5).abs() < 1e-5);
+        assert!((level.get_pixel(1, 0)[0] - (2.0 * 0.5 + 3.0 + 4.0) / 2.5).abs() < 1e-5);
+    }
+
+    #[test]
+    fn srgb_filters_in_linear_space() {
+        let source = image::RgbaImage::from_fn(2, 2, |x, _| image::Rgba(if x == 0 { [0, 0, 0, 0] } else { [255; 4] }));
+        let expected = (color::linear_to_srgb(0.5) * 255.0).round() as u8;
+        assert_eq!(downsample(&source, true).get_pixel(0, 0).0, [expected, expected, expected, 128]);
+        assert_eq!(downsample(&source, false).get_pixel(0, 0).0, [128; 4]);
+    }
+
+    #[test]
+    fn gpu_matches_cpu() {
+        let Some((device, queue)) = testing::device() else {
+            return;
+        };
+        for format in [wgpu::TextureFormat::Rgba8UnormSrgb, wgpu::TextureFormat::Rgba8Unorm] {
+            for (width, height) in [(64, 32), (13, 7), (1, 11)] {
+                let image = test_image(width, height);
+                let size = wgpu::Extent3d {
+                    width,
+                    height,
+                    depth_or_array_layers: 1,
+                };
+                let texture = device.create_texture(&wgpu::TextureDescriptor {
+                    label: Some("Mipmap Test Texture"),
+                    size,
+                    mip_level_count: mip_level_count(width, height),
+                    sample_count: 1,
+                    dimension: wgpu::TextureDimension::D2,
+                    format,
+                    usage: wgpu::TextureUsages::TEXTURE_BINDING
+                        | wgpu::TextureUsages::RENDER_ATTACHMENT
+                        | wgpu::TextureUsages::COPY_DST
+                        | wgpu::TextureUsages::COPY_SRC,
+                    view_formats: &[],
+                });
+                queue.write_texture(
+                    texture.as_image_copy(),
+                    &image,
+                    wgpu::ImageDataLayout {
+                        offset: 0,
+                        bytes_per_row: Some(4 * width),
+                        rows_per_image: Some(height),
+                    },
+                    size,
+                );
+                generate(&device, &queue, &texture);
+
+                let srgb = format.is_srgb();
+                for (level, expected) in cpu_mip_chain(&image, srgb).iter().enumerate() {
+                    let level = level as u32 + 1;
+                    let actual = testing::read_level(&device, &queue, &texture, level);
+                    // Rounding between levels can differ by a step
+                    let difference = testing::max_difference(&actual, expected);
+                    assert!(difference <= 2, "{:?} {}x{} level {} is off by {}", format, width, height, level, difference);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,118 @@
+// Downsamples one mip level into the next. Texels are read unfiltered and
+// decoded from sRGB where the format is, so the filtering happens in linear
+// space and the render target encodes the result again
+@group(0) @binding(0) var source: texture_2d<f32>;
+// Cubemaps are read through a cube view, since GL can't bind a single face of
+// one as a 2D texture. `source_sampler` is a nearest sampler, and sampling
+// exactly at texel centres returns the texel itself
+@group(0) @binding(1) var source_cube: texture_cube<f32>;
+@group(0) @binding(2) var source_sampler: sampler;
+// Likewise a single layer of an array, so those are read through an array view
+@group(0) @binding(3) var source_array: texture_2d_array<f32>;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    // Layer being rendered, taken from the instance index
+    @location(0) @interpolate(flat) face: u32,
+}
+
+// Fullscreen triangle
+@vertex
+fn vs_main(
+    @builtin(vertex_index) in_vertex_index: u32,
+    @builtin(instance_index) in_instance_index: u32,
+) -> VertexOutput {
+    let uv = vec2<f32>(f32((in_vertex_index << 1u) & 2u), f32(in_vertex_index & 2u));
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(uv * 2.0 - 1.0, 0.0, 1.0);
+    out.face = in_instance_index;
+    return out;
+}
+
+// Source texels covered by destination texel `x` along one axis. Even sizes
+// are a plain 2:1 box, odd sizes spread each destination texel over three
+// source texels weighted by how much of each it covers
+struct Footprint {
+    first: i32,
+    count: i32,
+    weights: vec3<f32>,
+}
+
+fn footprint(x: u32, source_size: u32) -> Footprint {
+    var f: Footprint;
+    if source_size == 1u {
+        f.first = 0;
+        f.count = 1;
+        f.weights = vec3<f32>(1.0, 0.0, 0.0);
+        return f;
+    }
+
+    f.first = i32(2u * x);
+    if source_size % 2u == 0u {
+        f.count = 2;
+        f.weights = vec3<f32>(0.5, 0.5, 0.0);
+    } else {
+        let ratio = f32(source_size) / f32(source_size / 2u);
+        let xf = f32(x);
+        f.count = 3;
+        f.weights = vec3<f32>(
+            (2.0 * xf + 1.0 - xf * ratio) / ratio,
+            1.0 / ratio,
+            ((xf + 1.0) * ratio - (2.0 * xf + 2.0)) / ratio,
+        );
+    }
+    return f;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let source_size = textureDimensions(source);
+    let texel = vec2<u32>(in.clip_position.xy);
+    let fx = footprint(texel.x, source_size.x);
+    let fy = footprint(texel.y, source_size.y);
+
+    var color = vec4<f32>(0.0);
+    for (var j = 0; j < fy.count; j++) {
+        for (var i = 0; i < fx.count; i++) {
+            let weight = fx.weights[i] * fy.weights[j];
+            color += textureLoad(source, vec2<i32>(fx.first + i, fy.first + j), 0) * weight;
+        }
+    }
+    return color;
+}
+
+@fragment
+fn fs_cube(in: VertexOutput) -> @location(0) vec4<f32> {
+    let source_size = textureDimensions(source_cube);
+    let texel = vec2<u32>(in.clip_position.xy);
+    let fx = footprint(texel.x, source_size.x);
+    let fy = footprint(texel.y, source_size.y);
+
+    var color = vec4<f32>(0.0);
+    for (var j = 0; j < fy.count; j++) {
+        for (var i = 0; i < fx.count; i++) {
+            let weight = fx.weights[i] * fy.weights[j];
+            let center = (vec2<f32>(vec2<i32>(fx.first + i, fy.first + j)) + 0.5) / vec2<f32>(source_size);
+            let direction = face_direction(in.face, center * 2.0 - 1.0);
+            color += textureSampleLevel(source_cube, source_sampler, direction, 0.0) * weight;
+        }
+    }
+    return color;
+}
+
+@fragment
+fn fs_array(in: VertexOutput) -> @location(0) vec4<f32> {
+    let source_size = textureDimensions(source_array);
+    let texel = vec2<u32>(in.clip_position.xy);
+    let fx = footprint(texel.x, source_size.x);
+    let fy = footprint(texel.y, source_size.y);
+
+    var color = vec4<f32>(0.0);
+    for (var j = 0; j < fy.count; j++) {
+        for (var i = 0; i < fx.count; i++) {
+            let weight = fx.weights[i] * fy.weights[j];
+            color += textureLoad(source_array, vec2<i32>(fx.first + i, fy.first + j), in.face, 0) * weight;
+        }
+    }
+    return color;
+}
@@ -0,0 +1,8 @@
+// Per-object transform, prepended after camera.wgsl
+struct ModelUniform {
+    model: mat4x4<f32>,
+};
+
+@group(2) @binding(0)
+var<uniform> model_transform: ModelUniform;
+
@@ -0,0 +1,151 @@
+use cgmath::InnerSpace;
+
+use crate::bounds::Aabb;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Ray {
+    pub origin: cgmath::Point3<f32>,
+    pub direction: cgmath::Vector3<f32>,
+}
+
+impl Ray {
+    pub fn new(origin: cgmath::Point3<f32>, direction: cgmath::Vector3<f32>) -> Self {
+        Self {
+            origin,
+            direction: direction.normalize(),
+        }
+    }
+
+    // Distances along the result only match this ray's for rigid transforms
+    pub fn transformed(&self, transform: &cgmath::Matrix4<f32>) -> Ray {
+        use cgmath::Transform;
+        Ray::new(transform.transform_point(self.origin), transform.transform_vector(self.direction))
+    }
+
+    pub fn at(&self, distance: f32) -> cgmath::Point3<f32> {
+        self.origin + self.direction * distance
+    }
+
+    // Moller-Trumbore, hits both faces of the triangle
+    pub fn intersect_triangle(&self, triangle: [cgmath::Point3<f32>; 3]) -> Option<f32> {
+        let [a, b, c] = triangle;
+        let edge1 = b - a;
+        let edge2 = c - a;
+        let p = self.direction.cross(edge2);
+        let det = edge1.dot(p);
+        if det.abs() < f32::EPSILON {
+            return None;
+        }
+
+        let inv_det = 1.0 / det;
+        let s = self.origin - a;
+        let u = s.dot(p) * inv_det;
+        if !(0.0..=1.0).contains(&u) {
+            return None;
+        }
+
+        let q = s.cross(edge1);
+        let v = self.direction.dot(q) * inv_det;
+        if v < 0.0 || u + v > 1.0 {
+            return None;
+        }
+
+        let distance = edge2.dot(q) * inv_det;
+        (distance >= 0.0).then_some(distance)
+    }
+
+    // Slab test; starting inside the box counts as a hit at distance 0
+    pub fn intersect_aabb(&self, aabb: &Aabb) -> Option<f32> {
+        let mut t_min = 0.0f32;
+        let mut t_max = f32::INFINITY;
+
+        for axis in 0..3 {
+            let inv_dir = 1.0 / self.direction[axis];
+            let mut t0 = (aabb.min[axis] - self.origin[axis]) * inv_dir;
+            let mut t1 = (aabb.max[axis] - self.origin[axis]) * inv_dir;
+            if inv_dir < 0.0 {
+                std::mem::swap(&mut t0, &mut t1);
+            }
+            // NaN comes from a zero direction component with the origin on the
+            // slab boundary; max/min ignore it, which keeps the hit
+            t_min = t_min.max(t0);
+            t_max = t_max.min(t1);
+            if t_max < t_min {
+                return None;
+            }
+        }
+
+        Some(t_min)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ray(origin: [f32; 3], direction: [f32; 3]) -> Ray {
+        Ray::new(origin.into(), direction.into())
+    }
+
+    fn triangle() -> [cgmath::Point3<f32>; 3] {
+        [
+            cgmath::Point3::new(0.0, 0.0, 0.0),
+            cgmath::Point3::new(1.0, 0.0, 0.0),
+            cgmath::Point3::new(0.0, 1.0, 0.0),
+        ]
+    }
+
+    fn unit_box() -> Aabb {
+        Aabb {
+            min: cgmath::Point3::new(-1.0, -1.0, -1.0),
+            max: cgmath::Point3::new(1.0, 1.0, 1.0),
+        }
+    }
+
+    fn assert_hit(hit: Option<f32>, expected: f32) {
+        let distance = hit.expect("should hit");
+        assert!((distance - expected).abs() < 1e-5, "hit at {}, expected {}", distance, expected);
+    }
+
+    #[test]
+    fn triangle_hits_and_misses() {
+        assert_hit(ray([0.25, 0.25, 2.0], [0.0, 0.0, -1.0]).intersect_triangle(triangle()), 2.0);
+        // From behind, since both faces count
+        assert_hit(ray([0.25, 0.25, -1.0], [0.0, 0.0, 1.0]).intersect_triangle(triangle()), 1.0);
+        // Past the hypotenuse, and outside either edge
+        assert_eq!(ray([0.75, 0.75, 1.0], [0.0, 0.0, -1.0]).intersect_triangle(triangle()), None);
+        assert_eq!(ray([-0.1, 0.5, 1.0], [0.0, 0.0, -1.0]).intersect_triangle(triangle()), None);
+        assert_eq!(ray([0.5, -0.1, 1.0], [0.0, 0.0, -1.0]).intersect_triangle(triangle()), None);
+    }
+
+    #[test]
+    fn triangle_parallel_or_behind_misses() {
+        assert_eq!(ray([-1.0, 0.25, 0.0], [1.0, 0.0, 0.0]).intersect_triangle(triangle()), None);
+        assert_eq!(ray([-1.0, 0.25, 1.0], [1.0, 0.0, 0.0]).intersect_triangle(triangle()), None);
+        // The triangle is at t = -1
+        assert_eq!(ray([0.25, 0.25, 1.0], [0.0, 0.0, 1.0]).intersect_triangle(triangle()), None);
+    }
+
+    #[test]
+    fn aabb_hits_and_misses() {
+        assert_hit(ray([0.0, 0.0, 5.0], [0.0, 0.0, -1.0]).intersect_aabb(&unit_box()), 4.0);
+        assert_hit(ray([-5.0, -5.0, -5.0], [1.0, 1.0, 1.0]).intersect_aabb(&unit_box()), 4.0 * 3f32.sqrt());
+        assert_eq!(ray([0.0, 0.0, 5.0], [1.0, 0.0, -1.0]).intersect_aabb(&unit_box()), None);
+        // The box is at t = -6 to -4
+        assert_eq!(ray([0.0, 0.0, 5.0], [0.0, 0.0, 1.0]).intersect_aabb(&unit_box()), None);
+    }
+
+    #[test]
+    fn aabb_parallel_to_a_slab() {
+        // Inside the x slab, outside it, and on its boundary
+        assert_hit(ray([0.5, 0.0, 5.0], [0.0, 0.0, -1.0]).intersect_aabb(&unit_box()), 4.0);
+        assert_eq!(ray([3.0, 0.0, 5.0], [0.0, 0.0, -1.0]).intersect_aabb(&unit_box()), None);
+        assert_hit(ray([1.0, 0.0, 5.0], [0.0, 0.0, -1.0]).intersect_aabb(&unit_box()), 4.0);
+    }
+
+    #[test]
+    fn aabb_from_inside() {
+        assert_hit(ray([0.0, 0.0, 0.0], [0.0, 1.0, 0.0]).intersect_aabb(&unit_box()), 0.0);
+        assert_hit(ray([0.9, -0.9, 0.5], [-1.0, 0.0, 0.0]).intersect_aabb(&unit_box()), 0.0);
+    }
+}
@@ -0,0 +1,552 @@
+// Test patterns generated into Rgba8Unorm textures. The GPU path runs
+// procedural.wgsl as a compute shader; the CPU path here follows it step by
+// step, both as the fallback where there are no compute shaders (WebGL2) and
+// so the output can be checked without a GPU
+
+use wgpu::util::DeviceExt;
+
+const WORKGROUP_SIZE: u32 = 8;
+// Later octaves would be finer than any texel
+const MAX_OCTAVES: u32 = 16;
+
+// `Params::pattern` for each Pattern, in declaration order. The shader gets
+// them as constants of the same names, see `shader_source`
+const CHECKER: u32 = 0;
+const GRADIENT: u32 = 1;
+const UV_GRID: u32 = 2;
+const PERLIN: u32 = 3;
+const SIMPLEX: u32 = 4;
+const VORONOI: u32 = 5;
+const PATTERN_IDS: [(&str, u32); 6] = [
+    ("CHECKER", CHECKER),
+    ("GRADIENT", GRADIENT),
+    ("UV_GRID", UV_GRID),
+    ("PERLIN", PERLIN),
+    ("SIMPLEX", SIMPLEX),
+    ("VORONOI", VORONOI),
+];
+
+// Colours are stored as given, without any sRGB conversion
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Pattern {
+    // `cells` squares across and down, starting with the first colour
+    Checker { cells: u32, colors: [[u8; 4]; 2] },
+    // Left to right
+    #[allow(unused)]
+    Gradient { from: [u8; 4], to: [u8; 4] },
+    // U in red and V in green, divided into `cells` by white lines
+    #[allow(unused)]
+    UvGrid { cells: u32 },
+    // Grey fractal noise. The first octave has `cells` cells a side and each
+    // one after doubles them
+    #[allow(unused)]
+    Perlin { cells: u32, octaves: u32, seed: u32 },
+    // Grey fractal noise on a triangular grid, with fewer axis-aligned
+    // artifacts than Perlin. Octaves work the same way, but it doesn't tile
+    #[allow(unused)]
+    Simplex { cells: u32, octaves: u32, seed: u32 },
+    // Distance from each texel to the nearest of one random point per cell,
+    // measured in cells
+    #[allow(unused)]
+    Voronoi { cells: u32, seed: u32 },
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct Params {
+    pattern: u32,
+    cells: u32,
+    octaves: u32,
+    seed: u32,
+    color_a: [f32; 4],
+    color_b: [f32; 4],
+}
+
+impl Pattern {
+    // `size` is the texture being filled
+    fn params(&self, size: [u32; 2]) -> Params {
+        let unorm = |color: [u8; 4]| color.map(|c| c as f32 / 255.0);
+        let mut params = Params {
+            pattern: CHECKER,
+            cells: 1,
+            octaves: 1,
+            seed: 0,
+            color_a: [0.0; 4],
+            color_b: [0.0; 4],
+        };
+        match *self {
+            Pattern::Checker { cells, colors } => {
+                params.pattern = CHECKER;
+                params.cells = cells;
+                params.color_a = unorm(colors[0]);
+                params.color_b = unorm(colors[1]);
+            },
+            Pattern::Gradient { from, to } => {
+                params.pattern = GRADIENT;
+                params.color_a = unorm(from);
+                params.color_b = unorm(to);
+            },
+            Pattern::UvGrid { cells } => {
+                params.pattern = UV_GRID;
+                params.cells = cells;
+            },
+            Pattern::Perlin { cells, octaves, seed } => {
+                params.pattern = PERLIN;
+                params.cells = cells;
+                params.octaves = octaves;
+                params.seed = seed;
+            },
+            Pattern::Simplex { cells, octaves, seed } => {
+                params.pattern = SIMPLEX;
+                params.cells = cells;
+                params.octaves = octaves;
+                params.seed = seed;
+            },
+            Pattern::Voronoi { cells, seed } => {
+                params.pattern = VORONOI;
+                params.cells = cells;
+                params.seed = seed;
+            },
+        }
+        // Every pattern divides by these
+        params.octaves = params.octaves.clamp(1, MAX_OCTAVES);
+        // `texel * cells`, the finest octave's `cells << (octaves - 1)` and
+        // Voronoi's cells either side of the edge all have to fit, since the
+        // shader would wrap silently where Rust panics
+        let largest = size[0].max(size[1]).max(1);
+        let max_cells = (u32::MAX / largest).min(i32::MAX as u32 / 3) >> (params.octaves - 1);
+        params.cells = params.cells.clamp(1, max_cells.max(1));
+        params
+    }
+}
+
+// procedural.wgsl with the pattern ids defined ahead of it
+fn shader_source() -> String {
+    let ids: String = PATTERN_IDS.iter().map(|(name, id)| format!("const {}: u32 = {}u;\n", name, id)).collect();
+    ids + include_str!("procedural.wgsl")
+}
+
+// WebGL2 has no compute shaders, which shows as zero compute limits
+pub fn supports_compute(device: &wgpu::Device) -> bool {
+    let limits = device.limits();
+    limits.max_compute_workgroup_size_x >= WORKGROUP_SIZE
+        && limits.max_compute_workgroup_size_y >= WORKGROUP_SIZE
+        && limits.max_compute_invocations_per_workgroup >= WORKGROUP_SIZE * WORKGROUP_SIZE
+        && limits.max_storage_textures_per_shader_stage >= 1
+}
+
+// Fills level 0 of `texture`, which must be Rgba8Unorm with STORAGE_BINDING
+// usage
+pub fn generate_gpu(device: &wgpu::Device, queue: &wgpu::Queue, pattern: &Pattern, texture: &wgpu::Texture) {
+    let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some("Procedural Shader"),
+        source: wgpu::ShaderSource::Wgsl(shader_source().into()),
+    });
+    let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        entries: &[
+            wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::COMPUTE,
+                ty: wgpu::BindingType::StorageTexture {
+                    access: wgpu::StorageTextureAccess::WriteOnly,
+                    format: wgpu::TextureFormat::Rgba8Unorm,
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                },
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 1,
+                visibility: wgpu::ShaderStages::COMPUTE,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+        ],
+        label: Some("procedural_bind_group_layout"),
+    });
+    let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: Some("Procedural Pipeline Layout"),
+        bind_group_layouts: &[&bind_group_layout],
+        push_constant_ranges: &[],
+    });
+    let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+        label: Some("Procedural Pipeline"),
+        layout: Some(&pipeline_layout),
+        module: &shader,
+        entry_point: "cs_main",
+        compilation_options: wgpu::PipelineCompilationOptions::default(),
+        cache: None,
+    });
+
+    let params_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("Procedural Params Buffer"),
+        contents: bytemuck::cast_slice(&[pattern.params([texture.width(), texture.height()])]),
+        usage: wgpu::BufferUsages::UNIFORM,
+    });
+    let view = texture.create_view(&wgpu::TextureViewDescriptor {
+        label: Some("procedural_target_view"),
+        dimension: Some(wgpu::TextureViewDimension::D2),
+        mip_level_count: Some(1),
+        ..Default::default()
+    });
+    let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+        layout: &bind_group_layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(&view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: params_buffer.as_entire_binding(),
+            },
+        ],
+        label: Some("procedural_bind_group"),
+    });
+
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+        label: Some("Procedural Encoder"),
+    });
+    {
+        let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Procedural Pass"),
+            timestamp_writes: None,
+        });
+        pass.set_pipeline(&pipeline);
+        pass.set_bind_group(0, &bind_group, &[]);
+        pass.dispatch_workgroups(texture.width().div_ceil(WORKGROUP_SIZE), texture.height().div_ceil(WORKGROUP_SIZE), 1);
+    }
+    queue.submit(std::iter::once(encoder.finish()));
+}
+
+pub fn generate_cpu(pattern: &Pattern, width: u32, height: u32) -> image::RgbaImage {
+    let size = [width, height];
+    let params = pattern.params(size);
+    image::RgbaImage::from_fn(width, height, |x, y| {
+        let texel = [x, y];
+        let color = match params.pattern {
+            CHECKER => checker(&params, texel, size),
+            GRADIENT => gradient(&params, texel, size),
+            UV_GRID => uv_grid(&params, texel, size),
+            PERLIN => perlin(&params, texel, size),
+            SIMPLEX => simplex(&params, texel, size),
+            _ => voronoi(&params, texel, size),
+        };
+        image::Rgba(color.map(|c| (c.clamp(0.0, 1.0) * 255.0).round() as u8))
+    })
+}
+
+// Everything below mirrors the function of the same name in procedural.wgsl
+
+fn pcg(value: u32) -> u32 {
+    let state = value.wrapping_mul(747796405).wrapping_add(2891336453);
+    let word = ((state >> ((state >> 28) + 4)) ^ state).wrapping_mul(277803737);
+    (word >> 22) ^ word
+}
+
+fn hash(x: u32, y: u32, seed: u32) -> u32 {
+    pcg(x ^ pcg(y ^ pcg(seed)))
+}
+
+fn unit(h: u32) -> f32 {
+    (h >> 8) as f32 / 16777216.0
+}
+
+fn uv(texel: [u32; 2], size: [u32; 2]) -> [f32; 2] {
+    [0, 1].map(|i| (texel[i] as f32 + 0.5) / size[i] as f32)
+}
+
+fn checker(params: &Params, texel: [u32; 2], size: [u32; 2]) -> [f32; 4] {
+    let cell = texel[0] * params.cells / size[0] + texel[1] * params.cells / size[1];
+    if cell.is_multiple_of(2) { params.color_a } else { params.color_b }
+}
+
+fn gradient(params: &Params, texel: [u32; 2], size: [u32; 2]) -> [f32; 4] {
+    let t = uv(texel, size)[0];
+    [0, 1, 2, 3].map(|i| params.color_a[i] * (1.0 - t) + params.color_b[i] * t)
+}
+
+fn uv_grid(params: &Params, texel: [u32; 2], size: [u32; 2]) -> [f32; 4] {
+    let line = (texel[0] * params.cells) % size[0] < params.cells || (texel[1] * params.cells) % size[1] < params.cells;
+    if line {
+        return [1.0; 4];
+    }
+    let [u, v] = uv(texel, size);
+    [u, v, 0.0, 1.0]
+}
+
+fn fade(t: f32) -> f32 {
+    t * t * t * (t * (t * 6.0 - 15.0) + 10.0)
+}
+
+fn corner(h: u32, dx: f32, dy: f32) -> f32 {
+    match h & 7 {
+        0 => dx + dy,
+        1 => -dx + dy,
+        2 => dx - dy,
+        3 => -dx - dy,
+        4 => dx,
+        5 => -dx,
+        6 => dy,
+        _ => -dy,
+    }
+}
+
+fn lerp(a: f32, b: f32, t: f32) -> f32 {
+    a + (b - a) * t
+}
+
+fn perlin_octave(p: [f32; 2], period: u32, seed: u32) -> f32 {
+    let cell = p.map(f32::floor);
+    let f = [p[0] - cell[0], p[1] - cell[1]];
+    let x0 = cell[0] as u32 % period;
+    let y0 = cell[1] as u32 % period;
+    let x1 = (x0 + 1) % period;
+    let y1 = (y0 + 1) % period;
+    let n00 = corner(hash(x0, y0, seed), f[0], f[1]);
+    let n10 = corner(hash(x1, y0, seed), f[0] - 1.0, f[1]);
+    let n01 = corner(hash(x0, y1, seed), f[0], f[1] - 1.0);
+    let n11 = corner(hash(x1, y1, seed), f[0] - 1.0, f[1] - 1.0);
+    let u = fade(f[0]);
+    lerp(lerp(n00, n10, u), lerp(n01, n11, u), fade(f[1]))
+}
+
+fn perlin(params: &Params, texel: [u32; 2], size: [u32; 2]) -> [f32; 4] {
+    let mut sum = 0.0;
+    let mut total = 0.0;
+    let mut amplitude = 1.0;
+    for octave in 0..params.octaves {
+        let period = params.cells << octave;
+        let p = uv(texel, size).map(|c| c * period as f32);
+        sum += perlin_octave(p, period, params.seed.wrapping_add(octave)) * amplitude;
+        total += amplitude;
+        amplitude *= 0.5;
+    }
+    let value = (0.5 + 0.5 * sum / total).clamp(0.0, 1.0);
+    [value, value, value, 1.0]
+}
+
+const SKEW: f32 = 0.36602542;
+const UNSKEW: f32 = 0.21132487;
+
+fn simplex_corner(h: u32, x: f32, y: f32) -> f32 {
+    let t = 0.5 - x * x - y * y;
+    if t <= 0.0 {
+        return 0.0;
+    }
+    t * t * t * t * corner(h, x, y)
+}
+
+fn simplex_octave(p: [f32; 2], seed: u32) -> f32 {
+    let s = (p[0] + p[1]) * SKEW;
+    let i = (p[0] + s).floor();
+    let j = (p[1] + s).floor();
+    let t = (i + j) * UNSKEW;
+    let x0 = p[0] - (i - t);
+    let y0 = p[1] - (j - t);
+    let (i1, j1) = if x0 > y0 { (1, 0) } else { (0, 1) };
+    let x1 = x0 - i1 as f32 + UNSKEW;
+    let y1 = y0 - j1 as f32 + UNSKEW;
+    let x2 = x0 - 1.0 + 2.0 * UNSKEW;
+    let y2 = y0 - 1.0 + 2.0 * UNSKEW;
+    let (ci, cj) = (i as u32, j as u32);
+    let n0 = simplex_corner(hash(ci, cj, seed), x0, y0);
+    let n1 = simplex_corner(hash(ci.wrapping_add(i1), cj.wrapping_add(j1), seed), x1, y1);
+    let n2 = simplex_corner(hash(ci.wrapping_add(1), cj.wrapping_add(1), seed), x2, y2);
+    70.0 * (n0 + n1 + n2)
+}
+
+fn simplex(params: &Params, texel: [u32; 2], size: [u32; 2]) -> [f32; 4] {
+    let mut sum = 0.0;
+    let mut total = 0.0;
+    let mut amplitude = 1.0;
+    for octave in 0..params.octaves {
+        let frequency = params.cells << octave;
+        let p = uv(texel, size).map(|c| c * frequency as f32);
+        sum += simplex_octave(p, params.seed.wrapping_add(octave)) * amplitude;
+        total += amplitude;
+        amplitude *= 0.5;
+    }
+    let value = (0.5 + 0.5 * sum / total).clamp(0.0, 1.0);
+    [value, value, value, 1.0]
+}
+
+fn voronoi(params: &Params, texel: [u32; 2], size: [u32; 2]) -> [f32; 4] {
+    let cells = params.cells as i32;
+    let p = uv(texel, size).map(|c| c * params.cells as f32);
+    let cell = p.map(|c| c.floor() as i32);
+    let mut nearest = 1.0f32;
+    for j in -1..=1 {
+        for i in -1..=1 {
+            let neighbour = [cell[0] + i, cell[1] + j];
+            let wrapped = neighbour.map(|c| ((c + cells) % cells) as u32);
+            let h = hash(wrapped[0], wrapped[1], params.seed);
+            let point = [neighbour[0] as f32 + unit(h), neighbour[1] as f32 + unit(pcg(h))];
+            let distance = ((p[0] - point[0]).powi(2) + (p[1] - point[1]).powi(2)).sqrt();
+            nearest = nearest.min(distance);
+        }
+    }
+    [nearest, nearest, nearest, 1.0]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    const RED: [u8; 4] = [255, 0, 0, 255];
+    const BLUE: [u8; 4] = [0, 0, 255, 255];
+
+    fn patterns() -> [Pattern; 6] {
+        [
+            Pattern::Checker { cells: 4, colors: [RED, BLUE] },
+            Pattern::Gradient { from: RED, to: BLUE },
+            Pattern::UvGrid { cells: 4 },
+            Pattern::Perlin { cells: 4, octaves: 3, seed: 7 },
+            Pattern::Simplex { cells: 4, octaves: 3, seed: 7 },
+            Pattern::Voronoi { cells: 4, seed: 7 },
+        ]
+    }
+
+    #[test]
+    fn checker_texels() {
+        let image = generate_cpu(&Pattern::Checker { cells: 4, colors: [RED, BLUE] }, 16, 8);
+        assert_eq!(image.get_pixel(0, 0).0, RED);
+        assert_eq!(image.get_pixel(3, 1).0, RED);
+        assert_eq!(image.get_pixel(4, 0).0, BLUE);
+        assert_eq!(image.get_pixel(0, 2).0, BLUE);
+        assert_eq!(image.get_pixel(15, 7).0, RED);
+    }
+
+    #[test]
+    fn gradient_texels() {
+        let image = generate_cpu(&Pattern::Gradient { from: RED, to: BLUE }, 4, 1);
+        // Texel centres, an eighth of the way in from each end
+        assert_eq!(image.get_pixel(0, 0).0, [223, 0, 32, 255]);
+        assert_eq!(image.get_pixel(3, 0).0, [32, 0, 223, 255]);
+    }
+
+    #[test]
+    fn uv_grid_texels() {
+        let image = generate_cpu(&Pattern::UvGrid { cells: 2 }, 8, 4);
+        assert_eq!(image.get_pixel(0, 1).0, [255; 4]);
+        assert_eq!(image.get_pixel(4, 1).0, [255; 4]);
+        assert_eq!(image.get_pixel(2, 0).0, [255; 4]);
+        // (1.5 / 8, 1.5 / 4)
+        assert_eq!(image.get_pixel(1, 1).0, [48, 96, 0, 255]);
+    }
+
+    #[test]
+    fn noise_stays_in_range_and_varies() {
+        for pattern in &patterns()[3..] {
+            let image = generate_cpu(pattern, 32, 32);
+            let (min, max) = image.pixels().fold((255, 0), |(min, max), p| (p[0].min(min), p[0].max(max)));
+            assert!(max - min > 64, "{:?} only spans {}..{}", pattern, min, max);
+            assert!(image.pixels().all(|p| p[0] == p[1] && p[1] == p[2] && p[3] == 255));
+        }
+    }
+
+    #[test]
+    fn seeds_are_deterministic() {
+        for pattern in &patterns()[3..] {
+            assert_eq!(generate_cpu(pattern, 16, 16), generate_cpu(pattern, 16, 16));
+            let reseeded = match *pattern {
+                Pattern::Perlin { cells, octaves, .. } => Pattern::Perlin { cells, octaves, seed: 8 },
+                Pattern::Simplex { cells, octaves, .. } => Pattern::Simplex { cells, octaves, seed: 8 },
+                Pattern::Voronoi { cells, .. } => Pattern::Voronoi { cells, seed: 8 },
+                _ => unreachable!(),
+            };
+            assert_ne!(generate_cpu(pattern, 16, 16), generate_cpu(&reseeded, 16, 16), "{:?}", pattern);
+        }
+    }
+
+    // One texture width further on the pattern starts over
+    #[test]
+    fn tiles() {
+        let size = [24, 16];
+        let tiling = [
+            Pattern::Checker { cells: 4, colors: [RED, BLUE] },
+            Pattern::Perlin { cells: 3, octaves: 4, seed: 1 },
+            Pattern::Voronoi { cells: 5, seed: 1 },
+        ];
+        for pattern in tiling {
+            let params = pattern.params(size);
+            let sample = |texel: [u32; 2]| match params.pattern {
+                CHECKER => checker(&params, texel, size),
+                PERLIN => perlin(&params, texel, size),
+                _ => voronoi(&params, texel, size),
+            };
+            for y in 0..size[1] {
+                for x in 0..size[0] {
+                    let texel = sample([x, y]);
+                    for shifted in [sample([x + size[0], y]), sample([x, y + size[1]])] {
+                        let difference = (0..4).map(|i| (texel[i] - shifted[i]).abs()).fold(0.0, f32::max);
+                        assert!(difference < 1e-3, "{:?} doesn't tile at {}, {}", pattern, x, y);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn large_cell_counts_are_clamped() {
+        let size = [4096, 4096];
+        for pattern in [
+            Pattern::Checker { cells: u32::MAX, colors: [RED, BLUE] },
+            Pattern::Perlin { cells: u32::MAX, octaves: MAX_OCTAVES, seed: 0 },
+            Pattern::Voronoi { cells: u32::MAX, seed: 0 },
+        ] {
+            let params = pattern.params(size);
+            assert!((params.cells << (params.octaves - 1)).checked_mul(size[0]).is_some());
+            assert!((params.cells as i32).checked_mul(3).is_some());
+        }
+        // Doesn't panic
+        generate_cpu(&Pattern::UvGrid { cells: u32::MAX }, 3, 3);
+        generate_cpu(&Pattern::Perlin { cells: u32::MAX, octaves: 100, seed: 0 }, 3, 3);
+    }
+
+    // So gpu_matches_cpu runs every branch of the shader's switch
+    #[test]
+    fn every_pattern_is_tested() {
+        let ids: Vec<u32> = patterns().iter().map(|pattern| pattern.params([1, 1]).pattern).collect();
+        let expected: Vec<u32> = PATTERN_IDS.iter().map(|(_, id)| *id).collect();
+        assert_eq!(ids, expected);
+        assert_eq!(expected, (0..PATTERN_IDS.len() as u32).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn gpu_matches_cpu() {
+        let Some((device, queue)) = testing::device() else {
+            return;
+        };
+        if !supports_compute(&device) {
+            eprintln!("No compute shaders, skipping the GPU comparison");
+            return;
+        }
+        let (width, height) = (61, 37);
+        for pattern in patterns() {
+            let texture = device.create_texture(&wgpu::TextureDescriptor {
+                label: Some("Procedural Test Texture"),
+                size: wgpu::Extent3d {
+                    width,
+                    height,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: wgpu::TextureFormat::Rgba8Unorm,
+                usage: wgpu::TextureUsages::STORAGE_BINDING | wgpu::TextureUsages::COPY_SRC,
+                view_formats: &[],
+            });
+            generate_gpu(&device, &queue, &pattern, &texture);
+            let actual = testing::read_level(&device, &queue, &texture, 0);
+            // Fused multiply-adds on the GPU can round a step differently
+            let difference = testing::max_difference(&actual, &generate_cpu(&pattern, width, height));
+            assert!(difference <= 1, "{:?} is off by {}", pattern, difference);
+        }
+    }
+}
@@ -0,0 +1,204 @@
+// Procedural patterns, one texel per invocation. Each matches its CPU
+// version in procedural.rs, so the hashing is done on integers and the
+// floating point steps are kept in the same order. The pattern ids, CHECKER
+// to VORONOI, are defined by procedural.rs ahead of this file
+
+struct Params {
+    pattern: u32,
+    cells: u32,
+    octaves: u32,
+    seed: u32,
+    color_a: vec4<f32>,
+    color_b: vec4<f32>,
+}
+
+@group(0) @binding(0) var output: texture_storage_2d<rgba8unorm, write>;
+@group(0) @binding(1) var<uniform> params: Params;
+
+// PCG hash, see "Hash Functions for GPU Rendering" (Jarzynski and Olano)
+fn pcg(value: u32) -> u32 {
+    let state = value * 747796405u + 2891336453u;
+    let word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
+    return (word >> 22u) ^ word;
+}
+
+fn hash(x: u32, y: u32, seed: u32) -> u32 {
+    return pcg(x ^ pcg(y ^ pcg(seed)));
+}
+
+// 0..1 from the top 24 bits, which f32 holds exactly
+fn unit(h: u32) -> f32 {
+    return f32(h >> 8u) / 16777216.0;
+}
+
+// Texel centre in 0..1
+fn uv(texel: vec2<u32>, size: vec2<u32>) -> vec2<f32> {
+    return (vec2<f32>(texel) + 0.5) / vec2<f32>(size);
+}
+
+fn checker(texel: vec2<u32>, size: vec2<u32>) -> vec4<f32> {
+    let cell = texel.x * params.cells / size.x + texel.y * params.cells / size.y;
+    if cell % 2u == 0u {
+        return params.color_a;
+    }
+    return params.color_b;
+}
+
+fn gradient(texel: vec2<u32>, size: vec2<u32>) -> vec4<f32> {
+    let t = uv(texel, size).x;
+    return params.color_a * (1.0 - t) + params.color_b * t;
+}
+
+fn uv_grid(texel: vec2<u32>, size: vec2<u32>) -> vec4<f32> {
+    // A line starts wherever the texel crosses into a new cell
+    let line = (texel.x * params.cells) % size.x < params.cells || (texel.y * params.cells) % size.y < params.cells;
+    if line {
+        return vec4<f32>(1.0);
+    }
+    let coords = uv(texel, size);
+    return vec4<f32>(coords, 0.0, 1.0);
+}
+
+fn fade(t: f32) -> f32 {
+    return t * t * t * (t * (t * 6.0 - 15.0) + 10.0);
+}
+
+// Dot product of the offset with one of eight gradients picked by `h`
+fn corner(h: u32, dx: f32, dy: f32) -> f32 {
+    switch h & 7u {
+        case 0u: { return dx + dy; }
+        case 1u: { return -dx + dy; }
+        case 2u: { return dx - dy; }
+        case 3u: { return -dx - dy; }
+        case 4u: { return dx; }
+        case 5u: { return -dx; }
+        case 6u: { return dy; }
+        default: { return -dy; }
+    }
+}
+
+fn lerp(a: f32, b: f32, t: f32) -> f32 {
+    return a + (b - a) * t;
+}
+
+// Gradient noise that repeats every `period` cells
+fn perlin_octave(p: vec2<f32>, period: u32, seed: u32) -> f32 {
+    let cell = floor(p);
+    let f = p - cell;
+    let x0 = u32(cell.x) % period;
+    let y0 = u32(cell.y) % period;
+    let x1 = (x0 + 1u) % period;
+    let y1 = (y0 + 1u) % period;
+    let n00 = corner(hash(x0, y0, seed), f.x, f.y);
+    let n10 = corner(hash(x1, y0, seed), f.x - 1.0, f.y);
+    let n01 = corner(hash(x0, y1, seed), f.x, f.y - 1.0);
+    let n11 = corner(hash(x1, y1, seed), f.x - 1.0, f.y - 1.0);
+    let u = fade(f.x);
+    return lerp(lerp(n00, n10, u), lerp(n01, n11, u), fade(f.y));
+}
+
+fn perlin(texel: vec2<u32>, size: vec2<u32>) -> vec4<f32> {
+    var sum = 0.0;
+    var total = 0.0;
+    var amplitude = 1.0;
+    for (var octave = 0u; octave < params.octaves; octave++) {
+        let period = params.cells << octave;
+        sum += perlin_octave(uv(texel, size) * f32(period), period, params.seed + octave) * amplitude;
+        total += amplitude;
+        amplitude *= 0.5;
+    }
+    let value = clamp(0.5 + 0.5 * sum / total, 0.0, 1.0);
+    return vec4<f32>(value, value, value, 1.0);
+}
+
+// Skews the square grid into triangles and back, (sqrt(3) - 1) / 2 and
+// (3 - sqrt(3)) / 6
+const SKEW: f32 = 0.36602542;
+const UNSKEW: f32 = 0.21132487;
+
+// Falls off to zero 0.5 away from the corner
+fn simplex_corner(h: u32, x: f32, y: f32) -> f32 {
+    let t = 0.5 - x * x - y * y;
+    if t <= 0.0 {
+        return 0.0;
+    }
+    return t * t * t * t * corner(h, x, y);
+}
+
+fn simplex_octave(p: vec2<f32>, seed: u32) -> f32 {
+    let s = (p.x + p.y) * SKEW;
+    let i = floor(p.x + s);
+    let j = floor(p.y + s);
+    let t = (i + j) * UNSKEW;
+    let x0 = p.x - (i - t);
+    let y0 = p.y - (j - t);
+    // Which of the two triangles in the skewed cell the point is in
+    var i1 = 0u;
+    var j1 = 1u;
+    if x0 > y0 {
+        i1 = 1u;
+        j1 = 0u;
+    }
+    let x1 = x0 - f32(i1) + UNSKEW;
+    let y1 = y0 - f32(j1) + UNSKEW;
+    let x2 = x0 - 1.0 + 2.0 * UNSKEW;
+    let y2 = y0 - 1.0 + 2.0 * UNSKEW;
+    let ci = u32(i);
+    let cj = u32(j);
+    let n0 = simplex_corner(hash(ci, cj, seed), x0, y0);
+    let n1 = simplex_corner(hash(ci + i1, cj + j1, seed), x1, y1);
+    let n2 = simplex_corner(hash(ci + 1u, cj + 1u, seed), x2, y2);
+    // Scales the sum to roughly -1..1
+    return 70.0 * (n0 + n1 + n2);
+}
+
+fn simplex(texel: vec2<u32>, size: vec2<u32>) -> vec4<f32> {
+    var sum = 0.0;
+    var total = 0.0;
+    var amplitude = 1.0;
+    for (var octave = 0u; octave < params.octaves; octave++) {
+        let frequency = params.cells << octave;
+        sum += simplex_octave(uv(texel, size) * f32(frequency), params.seed + octave) * amplitude;
+        total += amplitude;
+        amplitude *= 0.5;
+    }
+    let value = clamp(0.5 + 0.5 * sum / total, 0.0, 1.0);
+    return vec4<f32>(value, value, value, 1.0);
+}
+
+fn voronoi(texel: vec2<u32>, size: vec2<u32>) -> vec4<f32> {
+    let cells = i32(params.cells);
+    let p = uv(texel, size) * f32(params.cells);
+    let cell = vec2<i32>(floor(p));
+    var nearest = 1.0;
+    for (var j = -1; j <= 1; j++) {
+        for (var i = -1; i <= 1; i++) {
+            let neighbour = cell + vec2<i32>(i, j);
+            // Points are shared across the edges so the texture tiles
+            let wrapped = vec2<u32>((neighbour + cells) % cells);
+            let h = hash(wrapped.x, wrapped.y, params.seed);
+            let point = vec2<f32>(neighbour) + vec2<f32>(unit(h), unit(pcg(h)));
+            nearest = min(nearest, distance(p, point));
+        }
+    }
+    return vec4<f32>(nearest, nearest, nearest, 1.0);
+}
+
+@compute @workgroup_size(8, 8)
+fn cs_main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let size = textureDimensions(output);
+    if id.x >= size.x || id.y >= size.y {
+        return;
+    }
+
+    var color: vec4<f32>;
+    switch params.pattern {
+        case CHECKER: { color = checker(id.xy, size); }
+        case GRADIENT: { color = gradient(id.xy, size); }
+        case UV_GRID: { color = uv_grid(id.xy, size); }
+        case PERLIN: { color = perlin(id.xy, size); }
+        case SIMPLEX: { color = simplex(id.xy, size); }
+        case VORONOI, default: { color = voronoi(id.xy, size); }
+    }
+    textureStore(output, id.xy, color);
+}
@@ -0,0 +1,78 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+// The parts of a wgpu::SamplerDescriptor textures get to choose, hashable so
+// equal settings can share one sampler
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SamplerOptions {
+    pub address_mode_u: wgpu::AddressMode,
+    pub address_mode_v: wgpu::AddressMode,
+    pub address_mode_w: wgpu::AddressMode,
+    pub mag_filter: wgpu::FilterMode,
+    pub min_filter: wgpu::FilterMode,
+    pub mipmap_filter: wgpu::FilterMode,
+    // Only used when all three filters are Linear, wgpu rejects it otherwise
+    pub anisotropy_clamp: u16,
+    pub compare: Option<wgpu::CompareFunction>,
+}
+
+// The filtering textures have always had: linear up close, nearest further
+// away. Trilinear filtering is asked for where it's wanted
+impl Default for SamplerOptions {
+    fn default() -> Self {
+        Self {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Nearest,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            anisotropy_clamp: 1,
+            compare: None,
+        }
+    }
+}
+
+impl SamplerOptions {
+    fn descriptor(&self) -> wgpu::SamplerDescriptor<'static> {
+        let all_linear = [self.mag_filter, self.min_filter, self.mipmap_filter]
+            .iter()
+            .all(|filter| *filter == wgpu::FilterMode::Linear);
+        wgpu::SamplerDescriptor {
+            label: Some("cached_sampler"),
+            address_mode_u: self.address_mode_u,
+            address_mode_v: self.address_mode_v,
+            address_mode_w: self.address_mode_w,
+            mag_filter: self.mag_filter,
+            min_filter: self.min_filter,
+            mipmap_filter: self.mipmap_filter,
+            anisotropy_clamp: if all_linear { self.anisotropy_clamp.max(1) } else { 1 },
+            compare: self.compare,
+            ..Default::default()
+        }
+    }
+}
+
+// Hands out one sampler per distinct set of options. Locked internally so
+// textures created off the render thread can share it
+pub struct SamplerCache {
+    samplers: Mutex<HashMap<SamplerOptions, Arc<wgpu::Sampler>>>,
+}
+
+impl SamplerCache {
+    pub fn new() -> Self {
+        Self {
+            samplers: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn get(&self, device: &wgpu::Device, options: &SamplerOptions) -> Arc<wgpu::Sampler> {
+        let mut samplers = self.samplers.lock().unwrap();
+        samplers
+            .entry(*options)
+            .or_insert_with(|| Arc::new(device.create_sampler(&options.descriptor())))
+            .clone()
+    }
+}
@@ -0,0 +1,180 @@
+// Copying rendered frames back to the CPU and saving them as PNGs
+
+use std::path::{Path, PathBuf};
+
+use anyhow::*;
+
+use crate::color;
+
+// Frames waiting to be saved. A single screenshot and a recorded sequence can
+// both want the same frame
+pub struct Screenshots {
+    requested: Vec<PathBuf>,
+    // Directory and number of the next frame while recording
+    sequence: Option<(PathBuf, u32)>,
+}
+
+impl Screenshots {
+    pub fn new() -> Self {
+        Self {
+            requested: Vec::new(),
+            sequence: None,
+        }
+    }
+
+    // Saves the next rendered frame to `path`
+    pub fn request(&mut self, path: impl Into<PathBuf>) {
+        self.requested.push(path.into());
+    }
+
+    // Saves every frame from now on as frame_00000.png, frame_00001.png, ...
+    // in `directory`, overwriting any earlier recording there
+    pub fn start_sequence(&mut self, directory: impl Into<PathBuf>) -> Result<()> {
+        let directory = directory.into();
+        std::fs::create_dir_all(&directory)
+            .with_context(|| format!("Couldn't create {}", directory.display()))?;
+        self.sequence = Some((directory, 0));
+        Ok(())
+    }
+
+    // Returns how many frames were recorded
+    pub fn stop_sequence(&mut self) -> Option<u32> {
+        self.sequence.take().map(|(_, frames)| frames)
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.sequence.is_some()
+    }
+
+    // Where the frame about to be rendered should be saved, if anywhere
+    pub fn take_frame(&mut self) -> Vec<PathBuf> {
+        let mut paths = std::mem::take(&mut self.requested);
+        if let Some((directory, frame)) = &mut self.sequence {
+            paths.push(directory.join(format!("frame_{:05}.png", frame)));
+            *frame += 1;
+        }
+        paths
+    }
+}
+
+// `prefix_001.png` and so on, the first that doesn't exist yet
+pub fn next_numbered_path(prefix: &str) -> PathBuf {
+    (1..)
+        .map(|n| PathBuf::from(format!("{}_{:03}.png", prefix, n)))
+        .find(|path| !path.exists())
+        .unwrap()
+}
+
+// A texture copied into a buffer the CPU can map. Buffer rows have to be a
+// multiple of COPY_BYTES_PER_ROW_ALIGNMENT, so each is padded past the
+// texture's own width
+pub struct Readback {
+    buffer: wgpu::Buffer,
+    format: wgpu::TextureFormat,
+    width: u32,
+    height: u32,
+    padded_bytes_per_row: u32,
+}
+
+impl Readback {
+    // Records the copy into `encoder`. The texture needs COPY_SRC usage
+    pub fn copy(device: &wgpu::Device, encoder: &mut wgpu::CommandEncoder, texture: &wgpu::Texture) -> Result<Self> {
+        let format = texture.format();
+        let bytes_per_pixel = format.block_copy_size(None)
+            .filter(|_| format.block_dimensions() == (1, 1))
+            .with_context(|| format!("Can't read back {:?} textures", format))?;
+        let (width, height) = (texture.width(), texture.height());
+        let padded_bytes_per_row = wgpu::util::align_to(width * bytes_per_pixel, wgpu::COPY_BYTES_PER_ROW_ALIGNMENT);
+
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Readback Buffer"),
+            size: padded_bytes_per_row as wgpu::BufferAddress * height as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+        encoder.copy_texture_to_buffer(
+            texture.as_image_copy(),
+            wgpu::ImageCopyBuffer {
+                buffer: &buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(padded_bytes_per_row),
+                    rows_per_image: Some(height),
+                },
+            },
+            texture.size(),
+        );
+
+        Ok(Self {
+            buffer,
+            format,
+            width,
+            height,
+            padded_bytes_per_row,
+        })
+    }
+
+    // Blocks until the copy has run, so only call it once the encoder has
+    // been submitted. Alpha is dropped since the window is shown opaque
+    pub fn read(self, device: &wgpu::Device) -> Result<image::RgbaImage> {
+        let slice = self.buffer.slice(..);
+        let (sender, receiver) = std::sync::mpsc::channel();
+        slice.map_async(wgpu::MapMode::Read, move |result| {
+            let _ = sender.send(result);
+        });
+        device.poll(wgpu::Maintain::Wait);
+        receiver
+            .try_recv()
+            .context("Readback buffer wasn't mapped")?
+            .context("Couldn't map the readback buffer")?;
+
+        let data = slice.get_mapped_range();
+        let row_bytes = (self.width * self.format.block_copy_size(None).unwrap()) as usize;
+        let mut pixels = Vec::with_capacity((self.width * self.height * 4) as usize);
+        for row in data.chunks(self.padded_bytes_per_row as usize) {
+            to_rgba8(self.format, &row[..row_bytes], &mut pixels)?;
+        }
+        drop(data);
+        self.buffer.unmap();
+
+        Ok(image::RgbaImage::from_raw(self.width, self.height, pixels).unwrap())
+    }
+}
+
+// 8-bit surfaces hold what is shown on screen, sRGB encoded or not, so
+// their bytes go straight into the PNG. Float surfaces are linear
+fn to_rgba8(format: wgpu::TextureFormat, row: &[u8], pixels: &mut Vec<u8>) -> Result<()> {
+    use wgpu::TextureFormat::*;
+    match format {
+        Rgba8Unorm | Rgba8UnormSrgb => {
+            pixels.extend(row.chunks_exact(4).flat_map(|p| [p[0], p[1], p[2], 255]));
+        },
+        Bgra8Unorm | Bgra8UnormSrgb => {
+            pixels.extend(row.chunks_exact(4).flat_map(|p| [p[2], p[1], p[0], 255]));
+        },
+        Rgb10a2Unorm => {
+            pixels.extend(row.chunks_exact(4).flat_map(|p| {
+                let texel = u32::from_le_bytes([p[0], p[1], p[2], p[3]]);
+                let channel = |shift: u32| ((texel >> shift & 0x3ff) >> 2) as u8;
+                [channel(0), channel(10), channel(20), 255]
+            }));
+        },
+        Rgba16Float => {
+            pixels.extend(row.chunks_exact(8).flat_map(|p| {
+                let channel = |i: usize| {
+                    let linear = half::f16::from_le_bytes([p[2 * i], p[2 * i + 1]]).to_f32();
+                    (color::linear_to_srgb(linear.clamp(0.0, 1.0)) * 255.0).round() as u8
+                };
+                [channel(0), channel(1), channel(2), 255]
+            }));
+        },
+        format => bail!("Can't convert {:?} to RGBA8", format),
+    }
+    Ok(())
+}
+
+pub fn save_png(image: &image::RgbaImage, path: &Path) -> Result<()> {
+    image
+        .save_with_format(path, image::ImageFormat::Png)
+        .with_context(|| format!("Couldn't save {}", path.display()))
+}
@@ -0,0 +1,32 @@
+@group(0) @binding(0) var sky_texture: texture_cube<f32>;
+@group(0) @binding(1) var sky_sampler: sampler;
+
+// Vertex
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) ndc: vec2<f32>,
+}
+
+// Fullscreen triangle, drawn before anything else without touching depth
+@vertex
+fn vs_main(@builtin(vertex_index) in_vertex_index: u32) -> VertexOutput {
+    let uv = vec2<f32>(f32((in_vertex_index << 1u) & 2u), f32(in_vertex_index & 2u));
+    var out: VertexOutput;
+    out.ndc = uv * 2.0 - 1.0;
+    out.clip_position = vec4<f32>(out.ndc, 0.5, 1.0);
+    return out;
+}
+
+// Fragment
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    // Depth 0.5 is finite for both depth modes, including the infinite
+    // reverse-Z projection, so unprojecting it gives a point along the view ray
+    let point = camera.inv_view_proj * vec4<f32>(in.ndc, 0.5, 1.0);
+    var direction = point.xyz / point.w - camera.eye_position.xyz;
+    // Orthographic projections have parallel rays, all along the view direction
+    if camera.proj[3][3] != 0.0 {
+        direction = -camera.inv_view[2].xyz;
+    }
+    return textureSample(sky_texture, sky_sampler, direction);
+}
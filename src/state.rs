@@ -1,11 +1,31 @@
-use std::ops::Range;
+use std::{ops::Range, path::{Path, PathBuf}, time::Duration};
 
-use wgpu::{util::DeviceExt, BufferSlice, Texture};
+use anyhow::Context;
+
+use wgpu::{util::DeviceExt, BufferSlice};
 use winit::{
-    event::{ElementState, KeyEvent, MouseButton, WindowEvent}, keyboard::{KeyCode, PhysicalKey}, window::Window
+    dpi::PhysicalPosition,
+    event::{DeviceEvent, ElementState, KeyEvent, MouseButton, WindowEvent}, keyboard::{KeyCode, ModifiersState, PhysicalKey}, window::{CursorGrabMode, Window}
 };
 
-use crate::{camera::{self, CameraController, CameraUniform}, texture};
+use crate::{
+    arcball::Arcball,
+    atlas::{AtlasId, TextureAtlas, UvRect},
+    bookmarks::{Bookmark, Bookmarks, START_BOOKMARK},
+    bounds::Aabb,
+    camera::{self, CameraController, CameraMode, DepthMode, CameraTransition, CameraUniform, FreeFlyCamera, FreeFlyController, MotionSmoothing, OrbitCameraController},
+    camera_path::{CameraPath, Keyframe},
+    frustum::Frustum,
+    picking::Ray,
+    sampler::SamplerCache,
+    screenshot::{self, Readback, Screenshots},
+    skybox::Skybox,
+    texture,
+    texture_manager::{self, TextureHandle, TextureManager},
+    viewport::{Viewports, MAX_VIEWPORTS},
+};
+#[cfg(feature = "hot-reload")]
+use crate::hot_reload::{self, AssetWatcher};
 
 pub struct State<'a> {
     surface: wgpu::Surface<'a>,
@@ -20,18 +40,80 @@ pub struct State<'a> {
     window: &'a Window,
     clear: wgpu::Color,
     render_state: RenderPipelineState,
+    depth_texture: texture::Texture,
+    // Drawn in place of the clear colour when a sky image was found
+    skybox: Option<Skybox>,
+    samplers: SamplerCache,
+    screenshots: Screenshots,
+    // Shared by every viewport since they all draw into one depth texture
+    depth_mode: DepthMode,
+    // Shift+X turns depth writes off to see what the test alone does
+    depth_write: bool,
     shape_state: ShapeState,
+    texture_bind_group_layout: wgpu::BindGroupLayout,
+    // Recreated whenever the atlas is uploaded again
     diffuse_bind_group: wgpu::BindGroup,
-    diffuse_texture: texture::Texture,
-    camera: camera::Camera,
-    camera_uniform: CameraUniform,
+    atlas: TextureAtlas,
+    diffuse_id: AtlasId,
+    array_bind_group_layout: wgpu::BindGroupLayout,
+    // Same for the texture array
+    layers_bind_group: wgpu::BindGroup,
+    textures: TextureManager,
+    // The diffuse image read from disk, which replaces the built-in copy in
+    // the atlas once it's loaded
+    diffuse_image: TextureHandle,
+    layers_texture: TextureHandle,
+    viewports: Viewports,
+    // Each viewport's CameraUniform lives at `index * camera_uniform_stride`
+    // in `camera_buffer`, selected with a dynamic offset
+    camera_uniform_stride: wgpu::BufferAddress,
     camera_buffer: wgpu::Buffer,
     camera_bind_group: wgpu::BindGroup,
+    camera_mode: CameraMode,
     camera_controller: CameraController,
+    orbit_controller: OrbitCameraController,
+    free_fly_camera: FreeFlyCamera,
+    free_fly_controller: FreeFlyController,
+    camera_path: CameraPath,
+    modifiers: ModifiersState,
+    elapsed: Duration,
+    cursor_position: PhysicalPosition<f64>,
+    buttons_held: u32,
+    selected: Option<PickHit>,
+    // Index of the viewport being moved and the transition moving it
+    camera_transition: Option<(usize, CameraTransition)>,
+    arcball: Arcball,
+    // Shape being turned by the arcball, kept after release for the inertia
+    arcball_shape: Option<Shapes>,
+    bookmarks: Bookmarks,
+    // None if watching couldn't be started
+    #[cfg(feature = "hot-reload")]
+    assets: Option<AssetWatcher>,
+    // Kept to rebuild the skybox when the sky files change
+    #[cfg(feature = "hot-reload")]
+    camera_bind_group_layout: wgpu::BindGroupLayout,
 }
 
+const CAMERA_PATH_FILE: &str = "camera_path.txt";
+const CAMERA_BOOKMARKS_FILE: &str = "camera_bookmarks.txt";
+// Seconds between keyframes recorded with K
+const KEYFRAME_SPACING: f32 = 2.0;
+const CAMERA_TRANSITION: Duration = Duration::from_millis(400);
+// Either a panorama or six faces in +X, -X, +Y, -Y, +Z, -Z order
+const SKY_EQUIRECT_FILE: &str = "sky.hdr";
+const SKY_FACE_FILES: [&str; 6] = ["sky/px.png", "sky/nx.png", "sky/py.png", "sky/ny.png", "sky/pz.png", "sky/nz.png"];
+const SKY_FACE_SIZE: u32 = 1024;
+const DIFFUSE_FILE: &str = "src/happy-tree.png";
+// Pixels of edge bleed around each atlas image, enough for three mip levels
+const ATLAS_PADDING: u32 = 4;
+// Layers of the texture array shader, see VERTEX_LAYERS
+const LAYER_FILES: [&str; 2] = [DIFFUSE_FILE, "src/happy-tree-autumn.png"];
+// F12 saves screenshot_001.png and so on, Shift+F12 records every frame here
+const SCREENSHOT_PREFIX: &str = "screenshot";
+const SEQUENCE_DIRECTORY: &str = "frames";
+
 impl<'a> State<'a> {
-    pub async fn new(window: &'a Window) -> State<'a> {
+    pub async fn new(window: &'a Window) -> anyhow::Result<State<'a>> {
         let size = window.inner_size();
 
         let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
@@ -54,7 +136,7 @@ impl<'a> State<'a> {
 
         let (device, queue) = adapter.request_device(
             &wgpu::DeviceDescriptor {
-                required_features: wgpu::Features::empty(),
+                required_features: adapter.features() & texture::OPTIONAL_FEATURES,
                 required_limits: if cfg!(target_arch="wasm32") {
                     wgpu::Limits::downlevel_webgl2_defaults()
                 } else {
@@ -73,7 +155,9 @@ impl<'a> State<'a> {
             .copied()
             .unwrap_or(surface_caps.formats[0]);
         let config = wgpu::SurfaceConfiguration {
-            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            // Screenshots copy straight from the surface when it allows it,
+            // and are drawn again offscreen when it doesn't
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | (surface_caps.usages & wgpu::TextureUsages::COPY_SRC),
             format: surface_format,
             width: size.width,
             height: size.height,
@@ -82,44 +166,25 @@ impl<'a> State<'a> {
             view_formats: vec![],
             desired_maximum_frame_latency: 2,
         };
-        let diffuse_bytes = include_bytes!("happy-tree.png");
-        let diffuse_texture = texture::Texture::from_bytes(&device, &queue, diffuse_bytes, "happy-tree.png").unwrap();
+        let samplers = SamplerCache::new();
+        // The diffuse image's place holds the placeholder until the file has
+        // loaded, see `update_atlas`
+        let mut atlas = TextureAtlas::new(256, device.limits().max_texture_dimension_2d, ATLAS_PADDING);
+        let diffuse_id = atlas.insert(texture_manager::placeholder_image())?;
+        #[cfg(target_arch = "wasm32")]
+        if let Err(e) = built_in_diffuse().and_then(|image| atlas.replace(diffuse_id, image)) {
+            log::error!("{:#}", e);
+        }
+        atlas.upload(&device, &queue, &texture::TextureOptions::default(), &samplers)?;
 
-        let texture_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-            entries: &[
-                wgpu::BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: wgpu::ShaderStages::FRAGMENT,
-                    ty: wgpu::BindingType::Texture {
-                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
-                        view_dimension: wgpu::TextureViewDimension::D2,
-                        multisampled: false,
-                    },
-                    count: None,
-                },
-                wgpu::BindGroupLayoutEntry {
-                    binding: 1,
-                    visibility: wgpu::ShaderStages::FRAGMENT,
-                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
-                    count: None,
-                },
-            ],
-            label: Some("texture_bind_group_layout"),
-        });
-        let diffuse_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: &texture_bind_group_layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&diffuse_texture.view),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: wgpu::BindingResource::Sampler(&diffuse_texture.sampler),
-                },
-            ],
-            label: Some("diffuse_bind_group"),
-        });
+        let mut textures = TextureManager::new(&device, &queue, &samplers);
+        let diffuse_image = textures.load_image(asset_path(DIFFUSE_FILE), texture::TextureOptions::default());
+        let layers_texture = textures.load_array(&LAYER_FILES.map(asset_path), texture::TextureOptions::default());
+
+        let texture_bind_group_layout = texture::bind_group_layout(&device, wgpu::TextureViewDimension::D2, "texture_bind_group_layout");
+        let array_bind_group_layout = texture::bind_group_layout(&device, wgpu::TextureViewDimension::D2Array, "array_bind_group_layout");
+        let diffuse_bind_group = create_texture_bind_group(&device, &texture_bind_group_layout, atlas.texture().context("Texture atlas wasn't uploaded")?);
+        let layers_bind_group = create_texture_bind_group(&device, &array_bind_group_layout, textures.get(layers_texture));
 
         let clear = wgpu::Color {
             r: 0.1,
@@ -136,28 +201,45 @@ impl<'a> State<'a> {
             fovy: 45.0,
             znear: 0.1,
             zfar: 100.0,
+            projection: camera::Projection::Perspective,
+            depth_mode: camera::DepthMode::Standard,
         };
 
-        let mut camera_uniform = CameraUniform::new();
-        camera_uniform.update_view_proj(&camera);
+        let bookmarks_path = asset_path(CAMERA_BOOKMARKS_FILE);
+        let bookmarks = if bookmarks_path.exists() {
+            Bookmarks::load(&bookmarks_path).unwrap_or_else(|e| {
+                log::error!("{:#}", e);
+                Bookmarks::new()
+            })
+        } else {
+            Bookmarks::new()
+        };
+        let camera = bookmarks.get(START_BOOKMARK).map_or(camera, |b| b.applied_to(&camera));
 
-        let camera_buffer = device.create_buffer_init(
-            &wgpu::util::BufferInitDescriptor {
-                label: Some("Camera Buffer"),
-                contents: bytemuck::cast_slice(&[camera_uniform]),
-                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
-            }
+        let mut viewports = Viewports::new(camera);
+        viewports.resize(size);
+
+        let camera_uniform_size = std::mem::size_of::<CameraUniform>() as wgpu::BufferAddress;
+        let camera_uniform_stride = wgpu::util::align_to(
+            camera_uniform_size,
+            device.limits().min_uniform_buffer_offset_alignment as wgpu::BufferAddress,
         );
+        let camera_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Camera Buffer"),
+            size: camera_uniform_stride * MAX_VIEWPORTS as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
 
         let camera_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
             entries: &[
                 wgpu::BindGroupLayoutEntry {
                     binding: 0,
-                    visibility: wgpu::ShaderStages::VERTEX,
+                    visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
                     ty: wgpu::BindingType::Buffer {
                         ty: wgpu::BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
+                        has_dynamic_offset: true,
+                        min_binding_size: wgpu::BufferSize::new(camera_uniform_size),
                     },
                     count: None,
                 },
@@ -171,106 +253,72 @@ impl<'a> State<'a> {
             entries: &[
                 wgpu::BindGroupEntry {
                     binding: 0,
-                    resource: camera_buffer.as_entire_binding(),
+                    resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                        buffer: &camera_buffer,
+                        offset: 0,
+                        size: wgpu::BufferSize::new(camera_uniform_size),
+                    }),
                 },
             ],
         });
 
-        let standard_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
-            label: Some("Standard Shader"),
-            source: wgpu::ShaderSource::Wgsl(include_str!("standard_shader.wgsl").into()),
+        let model_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::VERTEX,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: true,
+                        min_binding_size: wgpu::BufferSize::new(std::mem::size_of::<ModelUniform>() as u64),
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("model_bind_group_layout"),
         });
+
         let render_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
             label: Some("Render Pipeline Layout"),
             bind_group_layouts: &[
                 &texture_bind_group_layout,
                 &camera_bind_group_layout,
+                &model_bind_group_layout,
             ],
             push_constant_ranges: &[],
         });
-        let standard_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("Standard Render Pipeline"),
-            layout: Some(&render_pipeline_layout),
-            vertex: wgpu::VertexState {
-                module: &standard_shader,
-                entry_point: "vs_main",
-                buffers: &[Vertex::desc()],
-                compilation_options: wgpu::PipelineCompilationOptions::default(),
-            },
-            fragment: Some(wgpu::FragmentState {
-                module: &standard_shader,
-                entry_point: "fs_main",
-                targets: &[Some(wgpu::ColorTargetState {
-                    format: config.format,
-                    blend: Some(wgpu::BlendState::REPLACE),
-                    write_mask: wgpu::ColorWrites::ALL,
-                })],
-                compilation_options: wgpu::PipelineCompilationOptions::default(),
-            }),
-            primitive: wgpu::PrimitiveState {
-                topology: wgpu::PrimitiveTopology::TriangleList,
-                strip_index_format: None,
-                front_face: wgpu::FrontFace::Ccw,
-                cull_mode: Some(wgpu::Face::Back),
-                unclipped_depth: false,
-                polygon_mode: wgpu::PolygonMode::Fill,
-                conservative: false,
-            },
-            depth_stencil: None,
-            multisample: wgpu::MultisampleState {
-                count: 1,
-                mask: !0,
-                alpha_to_coverage_enabled: false,
-            },
-            multiview: None,
-            cache: None,
-        });
-        let position_color_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
-            label: Some("Position Color Shader"),
-            source: wgpu::ShaderSource::Wgsl(include_str!("position_color_shader.wgsl").into()),
-        });
-        let position_color_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("Position Color Render Pipeline"),
-            layout: Some(&render_pipeline_layout),
-            vertex: wgpu::VertexState {
-                module: &position_color_shader,
-                entry_point: "vs_main",
-                buffers: &[],
-                compilation_options: wgpu::PipelineCompilationOptions::default(),
-            },
-            fragment: Some(wgpu::FragmentState {
-                module: &position_color_shader,
-                entry_point: "fs_main",
-                targets: &[Some(wgpu::ColorTargetState {
-                    format: config.format,
-                    blend: Some(wgpu::BlendState::REPLACE),
-                    write_mask: wgpu::ColorWrites::ALL,
-                })],
-                compilation_options: wgpu::PipelineCompilationOptions::default(),
-            }),
-            primitive: wgpu::PrimitiveState {
-                topology: wgpu::PrimitiveTopology::TriangleList,
-                strip_index_format: None,
-                front_face: wgpu::FrontFace::Ccw,
-                cull_mode: Some(wgpu::Face::Back),
-                unclipped_depth: false,
-                polygon_mode: wgpu::PolygonMode::Fill,
-                conservative: false,
-            },
-            depth_stencil: None,
-            multisample: wgpu::MultisampleState {
-                count: 1,
-                mask: !0,
-                alpha_to_coverage_enabled: false,
-            },
-            multiview: None,
-            cache: None,
+        let array_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Texture Array Pipeline Layout"),
+            bind_group_layouts: &[
+                &array_bind_group_layout,
+                &camera_bind_group_layout,
+                &model_bind_group_layout,
+            ],
+            push_constant_ranges: &[],
         });
-        let render_state = RenderPipelineState::new(standard_pipeline, position_color_pipeline);
-        let shape_state = ShapeState::new(&device);
-        let camera_controller = CameraController::new(0.2);
+        let depth_texture = texture::Texture::create_depth_texture(&device, &config, camera.depth_mode.compare_function(), "depth_texture", &samplers);
+        let render_state = RenderPipelineState::new(
+            &device,
+            render_pipeline_layout,
+            array_pipeline_layout,
+            config.format,
+            DepthConfig::for_mode(camera.depth_mode),
+        );
+        #[cfg(feature = "hot-reload")]
+        let assets = watch_assets(&textures);
+        let skybox = load_sky(&device, &queue, &samplers)
+            .map(|cubemap| Skybox::new(&device, cubemap, &camera_bind_group_layout, config.format));
+        let shape_state = ShapeState::new(&device, &model_bind_group_layout, atlas.uv_rect(diffuse_id).context("Diffuse image isn't in the texture atlas")?);
+        let smoothing = MotionSmoothing {
+            acceleration: Some(20.0),
+            damping: Some(10.0),
+        };
+        let camera_controller = CameraController::new(3.0).with_smoothing(smoothing);
+        let orbit_controller = OrbitCameraController::new(0.005, 0.1, 0.0015).with_smoothing(smoothing);
+        let free_fly_camera = FreeFlyCamera::from_camera(&viewports.active().camera);
+        let free_fly_controller = FreeFlyController::new(3.0, 0.003).with_smoothing(smoothing);
 
-        Self {
+        Ok(Self {
             window,
             surface,
             device,
@@ -279,15 +327,46 @@ impl<'a> State<'a> {
             size,
             clear,
             render_state,
+            depth_texture,
+            skybox,
+            samplers,
+            screenshots: Screenshots::new(),
+            depth_mode: camera.depth_mode,
+            depth_write: true,
             shape_state,
+            texture_bind_group_layout,
             diffuse_bind_group,
-            diffuse_texture,
-            camera,
-            camera_uniform,
+            atlas,
+            diffuse_id,
+            array_bind_group_layout,
+            layers_bind_group,
+            textures,
+            diffuse_image,
+            layers_texture,
+            viewports,
+            camera_uniform_stride,
             camera_buffer,
             camera_bind_group,
+            camera_mode: CameraMode::Keyboard,
             camera_controller,
-        }
+            orbit_controller,
+            free_fly_camera,
+            free_fly_controller,
+            camera_path: CameraPath::new(),
+            modifiers: ModifiersState::empty(),
+            elapsed: Duration::ZERO,
+            cursor_position: PhysicalPosition::new(0.0, 0.0),
+            buttons_held: 0,
+            selected: None,
+            camera_transition: None,
+            arcball: Arcball::new(4.0),
+            arcball_shape: None,
+            bookmarks,
+            #[cfg(feature = "hot-reload")]
+            assets,
+            #[cfg(feature = "hot-reload")]
+            camera_bind_group_layout,
+        })
     }
 
     pub fn alter_clear(&mut self) {
@@ -297,7 +376,7 @@ impl<'a> State<'a> {
     }
 
     pub fn window(&self) -> &Window {
-        &self.window
+        self.window
     }
 
     pub fn resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>) {
@@ -306,13 +385,55 @@ impl<'a> State<'a> {
             self.config.width = new_size.width;
             self.config.height = new_size.height;
             self.surface.configure(&self.device, &self.config);
+            self.viewports.resize(new_size);
+            self.depth_texture = texture::Texture::create_depth_texture(&self.device, &self.config, self.depth_mode.compare_function(), "depth_texture", &self.samplers);
         }
     }
 
     pub fn input(&mut self, event: &WindowEvent) -> bool {
+        // Cursor tracking and picking happen before the camera controllers
+        // get a chance to consume the events
+        match event {
+            WindowEvent::CursorMoved { position, .. } => {
+                self.cursor_position = *position;
+                if self.arcball.is_dragging() {
+                    self.arcball.drag(cgmath::Vector2::new(position.x as f32, position.y as f32));
+                }
+                // Input goes to the viewport under the cursor, except that a
+                // drag stays with the viewport it started in
+                if self.buttons_held == 0 && self.viewports.activate_at(*position, self.size) {
+                    self.sync_free_fly_camera();
+                }
+            },
+            WindowEvent::MouseInput { state: ElementState::Released, button, .. } => {
+                self.buttons_held = self.buttons_held.saturating_sub(1);
+                if *button == MouseButton::Right {
+                    self.arcball.end();
+                }
+            },
+            WindowEvent::MouseInput { state: ElementState::Pressed, button, .. } => {
+                self.buttons_held += 1;
+                match button {
+                    MouseButton::Left => self.pick_at_cursor(),
+                    MouseButton::Right => self.begin_arcball(),
+                    _ => {},
+                }
+            },
+            _ => {},
+        }
+
+        let consumed = match self.camera_mode {
+            CameraMode::Keyboard => self.camera_controller.process_events(event),
+            CameraMode::Orbit => self.orbit_controller.process_events(event),
+            CameraMode::FreeFly => self.free_fly_controller.process_events(event),
+        };
+        if consumed {
+            return true;
+        }
+
         match event {
-            WindowEvent::MouseInput { device_id: _, state: ElementState::Pressed, button: MouseButton::Left } => {
-                println!("{:?}", event);
+            WindowEvent::MouseInput { device_id: _, state: ElementState::Pressed, button: MouseButton::Left }
+                if self.selected.is_none() => {
                 self.alter_clear();
             },
             WindowEvent::KeyboardInput { event: KeyEvent {
@@ -330,17 +451,430 @@ impl<'a> State<'a> {
                 ..
             }, ..} => {
                 self.shape_state.swap();
+                // The selected shape isn't drawn any more
+                self.selected = None;
+            },
+            WindowEvent::KeyboardInput { event: KeyEvent {
+                physical_key: PhysicalKey::Code(KeyCode::KeyC),
+                state: ElementState::Pressed,
+                repeat: false,
+                ..
+            }, ..} => {
+                match self.camera_mode {
+                    CameraMode::Keyboard => self.camera_controller.release_all(),
+                    CameraMode::Orbit => self.orbit_controller.release_all(),
+                    CameraMode::FreeFly => self.free_fly_controller.release_all(),
+                }
+                self.camera_mode = self.camera_mode.next();
+                if self.camera_mode == CameraMode::FreeFly {
+                    self.sync_free_fly_camera();
+                } else {
+                    self.set_cursor_grab(false);
+                }
+                log::info!("Camera mode: {:?}", self.camera_mode);
+            },
+            WindowEvent::KeyboardInput { event: KeyEvent {
+                physical_key: PhysicalKey::Code(KeyCode::KeyP),
+                state: ElementState::Pressed,
+                repeat: false,
+                ..
+            }, ..} => {
+                let camera = &mut self.viewports.active_mut().camera;
+                camera.toggle_projection();
+                log::info!("Projection: {:?}", camera.projection);
+                self.sync_free_fly_camera();
+            },
+            WindowEvent::KeyboardInput { event: KeyEvent {
+                physical_key: PhysicalKey::Code(KeyCode::KeyF),
+                state: ElementState::Pressed,
+                repeat: false,
+                ..
+            }, ..} => {
+                self.frame_selection();
+            },
+            WindowEvent::KeyboardInput { event: KeyEvent {
+                physical_key: PhysicalKey::Code(KeyCode::KeyR),
+                state: ElementState::Pressed,
+                repeat: false,
+                ..
+            }, ..} => {
+                self.arcball.stop();
+                self.shape_state.reset_rotations();
+            },
+            WindowEvent::KeyboardInput { event: KeyEvent {
+                physical_key: PhysicalKey::Code(KeyCode::KeyX),
+                state: ElementState::Pressed,
+                repeat: false,
+                ..
+            }, ..} => {
+                if self.modifiers.shift_key() {
+                    self.depth_write = !self.depth_write;
+                    self.render_state.set_depth(&self.device, self.depth_config());
+                    log::info!("Depth write: {}", self.depth_write);
+                } else {
+                    self.set_depth_mode(self.depth_mode.toggled());
+                    log::info!("Depth mode: {:?}", self.depth_mode);
+                }
+            },
+            WindowEvent::KeyboardInput { event: KeyEvent {
+                physical_key: PhysicalKey::Code(KeyCode::KeyV),
+                state: ElementState::Pressed,
+                repeat: false,
+                ..
+            }, ..} => {
+                self.viewports.set_layout(self.viewports.layout().next());
+                self.viewports.resize(self.size);
+                self.viewports.activate_at(self.cursor_position, self.size);
+                self.sync_free_fly_camera();
+                log::info!("Viewport layout: {:?}", self.viewports.layout());
+            },
+            WindowEvent::KeyboardInput { event: KeyEvent {
+                physical_key: PhysicalKey::Code(KeyCode::KeyG),
+                state: ElementState::Pressed,
+                repeat: false,
+                ..
+            }, ..} if self.camera_mode == CameraMode::FreeFly => {
+                self.set_cursor_grab(!self.free_fly_controller.is_grabbed());
+            },
+            WindowEvent::Focused(false) => {
+                self.set_cursor_grab(false);
+            },
+            WindowEvent::ModifiersChanged(modifiers) => {
+                self.modifiers = modifiers.state();
+            },
+            WindowEvent::KeyboardInput { event: KeyEvent {
+                physical_key: PhysicalKey::Code(KeyCode::KeyK),
+                state: ElementState::Pressed,
+                repeat: false,
+                ..
+            }, ..} => {
+                if self.modifiers.shift_key() {
+                    self.camera_path.clear();
+                    log::info!("Cleared camera path");
+                } else {
+                    let time = self.camera_path.keyframes().last()
+                        .map_or(0.0, |k| k.time + KEYFRAME_SPACING);
+                    self.camera_path.add_keyframe(Keyframe::from_camera(&self.viewports.get(0).camera, time));
+                    log::info!("Added camera keyframe at {}s", time);
+                }
+            },
+            WindowEvent::KeyboardInput { event: KeyEvent {
+                physical_key: PhysicalKey::Code(KeyCode::KeyL),
+                state: ElementState::Pressed,
+                repeat: false,
+                ..
+            }, ..} => {
+                if self.modifiers.shift_key() {
+                    self.camera_path.looping = !self.camera_path.looping;
+                    log::info!("Camera path looping: {}", self.camera_path.looping);
+                } else {
+                    self.camera_path.toggle_play();
+                }
+            },
+            WindowEvent::KeyboardInput { event: KeyEvent {
+                physical_key: PhysicalKey::Code(KeyCode::F5),
+                state: ElementState::Pressed,
+                repeat: false,
+                ..
+            }, ..} => {
+                let path = asset_path(CAMERA_PATH_FILE);
+                match self.camera_path.save(&path) {
+                    Ok(()) => log::info!("Saved camera path to {}", path.display()),
+                    Err(e) => log::error!("{:#}", e),
+                }
+            },
+            WindowEvent::KeyboardInput { event: KeyEvent {
+                physical_key: PhysicalKey::Code(KeyCode::F9),
+                state: ElementState::Pressed,
+                repeat: false,
+                ..
+            }, ..} => {
+                let path = asset_path(CAMERA_PATH_FILE);
+                match CameraPath::load(&path) {
+                    Ok(camera_path) => {
+                        log::info!("Loaded {} keyframes from {}", camera_path.keyframes().len(), path.display());
+                        self.camera_path = camera_path;
+                    },
+                    Err(e) => log::error!("{:#}", e),
+                }
+            },
+            WindowEvent::KeyboardInput { event: KeyEvent {
+                physical_key: PhysicalKey::Code(KeyCode::F12),
+                state: ElementState::Pressed,
+                repeat: false,
+                ..
+            }, ..} => {
+                if !self.modifiers.shift_key() {
+                    self.screenshot(screenshot::next_numbered_path(SCREENSHOT_PREFIX));
+                } else if let Some(frames) = self.stop_recording() {
+                    log::info!("Recorded {} frames to {}", frames, SEQUENCE_DIRECTORY);
+                } else if let Err(e) = self.start_recording(SEQUENCE_DIRECTORY) {
+                    log::error!("{:#}", e);
+                }
+            },
+            WindowEvent::KeyboardInput { event: KeyEvent {
+                physical_key: PhysicalKey::Code(keycode),
+                state: ElementState::Pressed,
+                repeat: false,
+                ..
+            }, ..} => {
+                if let Some(name) = bookmark_slot(*keycode) {
+                    if self.modifiers.control_key() {
+                        self.save_bookmark(name);
+                    } else {
+                        self.recall_bookmark(name);
+                    }
+                }
             },
             _ => {},
         };
 
-        self.camera_controller.process_events(event)
+        false
+    }
+
+    fn save_bookmark(&mut self, name: &str) {
+        self.bookmarks.set(Bookmark::from_camera(name, &self.viewports.active().camera));
+        let path = asset_path(CAMERA_BOOKMARKS_FILE);
+        match self.bookmarks.save(&path) {
+            Ok(()) => log::info!("Saved camera bookmark {} to {}", name, path.display()),
+            Err(e) => log::error!("{:#}", e),
+        }
+    }
+
+    fn recall_bookmark(&mut self, name: &str) {
+        let Some(bookmark) = self.bookmarks.get(name) else {
+            log::info!("No camera bookmark {}", name);
+            return;
+        };
+        let camera = self.viewports.active().camera;
+        let transition = CameraTransition::new(camera, bookmark.applied_to(&camera), CAMERA_TRANSITION);
+        self.camera_transition = Some((self.viewports.active_index(), transition));
+    }
+
+    fn set_depth_mode(&mut self, depth_mode: DepthMode) {
+        self.depth_mode = depth_mode;
+        for index in 0..MAX_VIEWPORTS {
+            self.viewports.get_mut(index).camera.depth_mode = depth_mode;
+        }
+        self.render_state.set_depth(&self.device, self.depth_config());
+        // Its comparison sampler follows the mode too
+        self.depth_texture = texture::Texture::create_depth_texture(&self.device, &self.config, depth_mode.compare_function(), "depth_texture", &self.samplers);
+    }
+
+    fn depth_config(&self) -> DepthConfig {
+        DepthConfig::for_mode(self.depth_mode).with_write(self.depth_write)
+    }
+
+    pub fn device_input(&mut self, event: &DeviceEvent) -> bool {
+        match self.camera_mode {
+            CameraMode::FreeFly => self.free_fly_controller.process_device_events(event),
+            _ => false,
+        }
+    }
+
+    fn set_cursor_grab(&mut self, grab: bool) {
+        if grab {
+            // Locked isn't available everywhere (e.g. Windows), Confined is
+            // the closest fallback
+            let result = self.window.set_cursor_grab(CursorGrabMode::Locked)
+                .or_else(|_| self.window.set_cursor_grab(CursorGrabMode::Confined));
+            if let Err(e) = result {
+                log::warn!("Couldn't grab cursor: {}", e);
+                return;
+            }
+        } else {
+            let _ = self.window.set_cursor_grab(CursorGrabMode::None);
+        }
+        self.window.set_cursor_visible(!grab);
+        self.free_fly_controller.set_grabbed(grab);
+    }
+
+    // The free-fly camera keeps its own position and angles, so it has to
+    // catch up whenever the active camera is moved by something else
+    fn sync_free_fly_camera(&mut self) {
+        self.free_fly_camera = FreeFlyCamera::from_camera(&self.viewports.active().camera);
+    }
+
+    fn ray_at_cursor(&self) -> Option<Ray> {
+        let viewport = self.viewports.active();
+        let rect = viewport.pixel_rect(self.size);
+        viewport.camera.screen_ray(rect.local_position(self.cursor_position), rect.size())
+    }
+
+    fn pick_at_cursor(&mut self) {
+        let Some(ray) = self.ray_at_cursor() else {
+            return;
+        };
+        self.selected = self.shape_state.pick(&ray);
+        match &self.selected {
+            Some(hit) => log::info!(
+                "Picked {:?} triangle {} at {:?} ({} away) in {} view",
+                hit.shape, hit.triangle, hit.point, hit.distance, self.viewports.active().name,
+            ),
+            None => log::info!("Picked nothing"),
+        }
+    }
+
+    // Starts turning the shape under the cursor, with the arcball sized to
+    // the shape's bounding sphere on screen
+    fn begin_arcball(&mut self) {
+        let Some(hit) = self.ray_at_cursor().and_then(|ray| self.shape_state.pick(&ray)) else {
+            return;
+        };
+
+        let viewport = self.viewports.active();
+        let camera = &viewport.camera;
+        let rect = viewport.pixel_rect(self.size);
+        let sphere = self.shape_state.bounds_of(hit.shape).bounding_sphere();
+        let Some(center) = camera.project(sphere.center, rect.size()) else {
+            return;
+        };
+        let radius = camera
+            .project(sphere.center + camera.up * sphere.radius, rect.size())
+            .map_or(0.0, |edge| ((edge.x - center.x).powi(2) + (edge.y - center.y).powi(2)).sqrt());
+
+        self.arcball.begin(
+            cgmath::Vector2::new(self.cursor_position.x as f32, self.cursor_position.y as f32),
+            cgmath::Vector2::new((center.x + rect.x as f64) as f32, (center.y + rect.y as f64) as f32),
+            radius as f32,
+        );
+        self.arcball_shape = Some(hit.shape);
+    }
+
+    // Frames the selected shape, or everything that's drawn when nothing is
+    fn frame_selection(&mut self) {
+        let shape = self.selected.map_or(self.shape_state.state, |hit| hit.shape);
+        let sphere = self.shape_state.bounds_of(shape).bounding_sphere();
+        let camera = self.viewports.active().camera;
+        let transition = CameraTransition::new(camera, camera.framed(&sphere), CAMERA_TRANSITION);
+        self.camera_transition = Some((self.viewports.active_index(), transition));
+    }
+
+    pub fn update(&mut self, dt: Duration) {
+        self.elapsed += dt;
+        #[cfg(feature = "hot-reload")]
+        self.reload_changed_assets();
+        self.poll_textures();
+        let active = self.viewports.active_index();
+
+        // The path always drives the perspective viewport
+        let path_playing = self.camera_path.is_playing();
+        if path_playing {
+            self.camera_path.update(dt);
+            self.camera_path.apply(&mut self.viewports.get_mut(0).camera);
+        }
+
+        let mut transitioning = None;
+        if let Some((index, transition)) = &mut self.camera_transition {
+            transitioning = Some(*index);
+            if transition.update(&mut self.viewports.get_mut(*index).camera, dt) {
+                self.camera_transition = None;
+            }
+        }
+
+        if (path_playing && active == 0) || transitioning == Some(active) {
+            self.sync_free_fly_camera();
+        } else {
+            self.update_controllers(dt);
+        }
+
+        if let (Some(shape), Some(rotation)) = (self.arcball_shape, self.arcball.update(dt)) {
+            let rotation = self.viewports.active().camera.view_rotation_to_world(rotation);
+            self.shape_state.rotate(shape, rotation);
+        }
+        self.shape_state.write_models(&self.queue);
+
+        let time = self.elapsed.as_secs_f32();
+        for (index, viewport) in self.viewports.visible().iter().enumerate() {
+            let mut camera_uniform = CameraUniform::new();
+            camera_uniform.update_view_proj(&viewport.camera, viewport.pixel_rect(self.size).size(), time);
+            self.queue.write_buffer(
+                &self.camera_buffer,
+                index as wgpu::BufferAddress * self.camera_uniform_stride,
+                bytemuck::cast_slice(&[camera_uniform]),
+            );
+        }
+    }
+
+    #[cfg(feature = "hot-reload")]
+    fn reload_changed_assets(&mut self) {
+        let Some(assets) = &self.assets else {
+            return;
+        };
+        let changed = assets.changed();
+        if changed.is_empty() {
+            return;
+        }
+        self.render_state.reload_shaders(&self.device, &changed);
+        for path in &changed {
+            self.textures.reload(path);
+        }
+        // The current sky stays if the new files don't load
+        if changed.iter().any(|path| sky_files().any(|file| file.canonicalize().is_ok_and(|file| file == *path))) {
+            if let Some(cubemap) = load_sky(&self.device, &self.queue, &self.samplers) {
+                self.skybox = Some(Skybox::new(&self.device, cubemap, &self.camera_bind_group_layout, self.config.format));
+            }
+        }
+    }
+
+    fn poll_textures(&mut self) {
+        for (handle, result) in self.textures.poll(&self.device, &self.queue, &self.samplers) {
+            if let Err(e) = result {
+                log::error!("{:#}", e);
+                continue;
+            }
+            if handle == self.diffuse_image {
+                if let Err(e) = self.update_atlas() {
+                    log::error!("{:#}", e);
+                }
+            }
+            if handle == self.layers_texture {
+                self.layers_bind_group = create_texture_bind_group(&self.device, &self.array_bind_group_layout, self.textures.get(handle));
+            }
+        }
     }
 
-    pub fn update(&mut self) {
-        self.camera_controller.update_camera(&mut self.camera);
-        self.camera_uniform.update_view_proj(&self.camera);
-        self.queue.write_buffer(&self.camera_buffer, 0, bytemuck::cast_slice(&[self.camera_uniform]));
+    fn update_atlas(&mut self) -> anyhow::Result<()> {
+        let Some(image) = self.textures.image(self.diffuse_image) else {
+            return Ok(());
+        };
+        self.atlas.replace(self.diffuse_id, image.clone())?;
+        self.atlas.upload(&self.device, &self.queue, &texture::TextureOptions::default(), &self.samplers)?;
+        if let Some(texture) = self.atlas.texture() {
+            self.diffuse_bind_group = create_texture_bind_group(&self.device, &self.texture_bind_group_layout, texture);
+        }
+        // It can move when its size changes
+        if let Some(uv) = self.atlas.uv_rect(self.diffuse_id) {
+            self.shape_state.set_atlas_uv(&self.queue, uv);
+        }
+        Ok(())
+    }
+
+    fn update_controllers(&mut self, dt: Duration) {
+        let camera = &mut self.viewports.active_mut().camera;
+        match self.camera_mode {
+            CameraMode::Keyboard => self.camera_controller.update_camera(camera, dt),
+            CameraMode::Orbit => self.orbit_controller.update_camera(camera, dt),
+            CameraMode::FreeFly => {
+                self.free_fly_controller.update_camera(&mut self.free_fly_camera, dt);
+                self.free_fly_camera.apply(camera);
+            },
+        }
+    }
+
+    // Saves the next frame as a PNG
+    pub fn screenshot(&mut self, path: impl Into<PathBuf>) {
+        self.screenshots.request(path);
+    }
+
+    // Saves every frame as a numbered PNG in `directory` until stopped
+    pub fn start_recording(&mut self, directory: impl Into<PathBuf>) -> anyhow::Result<()> {
+        self.screenshots.start_sequence(directory)
+    }
+
+    // Returns the number of frames recorded, or None when not recording
+    pub fn stop_recording(&mut self) -> Option<u32> {
+        self.screenshots.stop_sequence()
     }
 
     pub fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
@@ -351,79 +885,431 @@ impl<'a> State<'a> {
         let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
             label: Some("Render Encoder"),
         });
+        self.draw_scene(&mut encoder, &view);
 
-        {
-            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                label: Some("Render Pass"),
-                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                    view: &view,
-                    resolve_target: None,
-                    ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(self.clear),
-                        store: wgpu::StoreOp::Store,
-                    },
-                })],
-                depth_stencil_attachment: None,
-                occlusion_query_set: None,
-                timestamp_writes: None,
+        let captures = self.screenshots.take_frame();
+        let readback = if captures.is_empty() {
+            None
+        } else if self.config.usage.contains(wgpu::TextureUsages::COPY_SRC) {
+            Some(Readback::copy(&self.device, &mut encoder, &output.texture))
+        } else {
+            let target = self.device.create_texture(&wgpu::TextureDescriptor {
+                label: Some("Screenshot Target"),
+                size: output.texture.size(),
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: self.config.format,
+                usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+                view_formats: &[],
             });
+            self.draw_scene(&mut encoder, &target.create_view(&wgpu::TextureViewDescriptor::default()));
+            Some(Readback::copy(&self.device, &mut encoder, &target))
+        };
+
+        self.queue.submit(std::iter::once(encoder.finish()));
+        output.present();
+
+        if let Some(readback) = readback {
+            let saved = readback
+                .and_then(|readback| readback.read(&self.device))
+                .and_then(|image| captures.iter().try_for_each(|path| screenshot::save_png(&image, path)));
+            if let Err(e) = saved {
+                log::error!("{:#}", e);
+            } else if !self.screenshots.is_recording() {
+                for path in &captures {
+                    log::info!("Saved screenshot to {}", path.display());
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn draw_scene(&self, encoder: &mut wgpu::CommandEncoder, view: &wgpu::TextureView) {
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Render Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(self.clear),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: &self.depth_texture.view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(self.depth_mode.clear_value()),
+                    store: wgpu::StoreOp::Store,
+                }),
+                stencil_ops: None,
+            }),
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
 
+        render_pass.set_vertex_buffer(0, self.shape_state.vertex_buffer_slice(self.render_state.state));
+        render_pass.set_vertex_buffer(1, self.shape_state.layer_buffer_slice());
+        render_pass.set_index_buffer(self.shape_state.index_buffer_slice(), wgpu::IndexFormat::Uint16);
+
+        let bounds = self.shape_state.bounds();
+        for (index, viewport) in self.viewports.visible().iter().enumerate() {
+            let rect = viewport.pixel_rect(self.size);
+            render_pass.set_viewport(rect.x as f32, rect.y as f32, rect.width as f32, rect.height as f32, 0.0, 1.0);
+            render_pass.set_scissor_rect(rect.x, rect.y, rect.width, rect.height);
+            let offset = index as wgpu::BufferAddress * self.camera_uniform_stride;
+            render_pass.set_bind_group(1, &self.camera_bind_group, &[offset as wgpu::DynamicOffset]);
+
+            if let Some(skybox) = &self.skybox {
+                skybox.draw(&mut render_pass);
+            }
             render_pass.set_pipeline(self.render_state.pipeline());
-            render_pass.set_bind_group(0, &self.diffuse_bind_group, &[]);
-            render_pass.set_bind_group(1, &self.camera_bind_group, &[]);
-            render_pass.set_vertex_buffer(0, self.shape_state.vertex_buffer_slice());
-            render_pass.set_index_buffer(self.shape_state.index_buffer_slice(), wgpu::IndexFormat::Uint16);
+            let texture_bind_group = match self.render_state.state {
+                RenderState::TextureArray => &self.layers_bind_group,
+                RenderState::Standard | RenderState::PositionColor => &self.diffuse_bind_group,
+            };
+            render_pass.set_bind_group(0, texture_bind_group, &[]);
+            render_pass.set_bind_group(2, &self.shape_state.model_bind_group, &[self.shape_state.model_offset()]);
 
-            render_pass.draw_indexed(self.shape_state.index_buffer_indices(), self.shape_state.base_vertex(), 0..1);
+            // cheap sphere rejection first, then the tighter box test
+            let frustum = Frustum::from_camera(&viewport.camera);
+            if frustum.intersects_sphere(&bounds.bounding_sphere()) && frustum.intersects_aabb(&bounds) {
+                render_pass.draw_indexed(self.shape_state.index_buffer_indices(), self.shape_state.base_vertex(), 0..1);
+            }
         }
+    }
+}
 
-        self.queue.submit(std::iter::once(encoder.finish()));
-        output.present();
+fn create_texture_bind_group(device: &wgpu::Device, layout: &wgpu::BindGroupLayout, texture: &texture::Texture) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(&texture.view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::Sampler(&texture.sampler),
+            },
+        ],
+        label: Some("diffuse_bind_group"),
+    })
+}
 
-        Ok(())
+fn load_sky(device: &wgpu::Device, queue: &wgpu::Queue, samplers: &SamplerCache) -> Option<texture::Texture> {
+    // Mip-mapped and mostly seen minified, so it's filtered trilinearly
+    let linear = wgpu::FilterMode::Linear;
+    let options = texture::TextureOptions::default().with_filters(linear, linear, linear);
+    let equirect = asset_path(SKY_EQUIRECT_FILE);
+    let faces = SKY_FACE_FILES.map(asset_path);
+    let cubemap = if equirect.exists() {
+        image::open(&equirect)
+            .with_context(|| format!("Couldn't load sky {}", equirect.display()))
+            .and_then(|img| texture::Texture::cube_from_equirect(device, queue, &img, SKY_FACE_SIZE, Some(SKY_EQUIRECT_FILE), &options, samplers))
+    } else if faces.iter().all(|file| file.exists()) {
+        faces
+            .iter()
+            .map(|file| image::open(file).with_context(|| format!("Couldn't load sky face {}", file.display())))
+            .collect::<anyhow::Result<Vec<_>>>()
+            .and_then(|faces| {
+                let faces: [image::DynamicImage; 6] = faces.try_into().unwrap();
+                texture::Texture::cube_from_faces(device, queue, &faces, Some("sky"), &options, samplers)
+            })
+    } else {
+        return None;
+    };
+
+    cubemap.map_err(|e| log::error!("{:#}", e)).ok()
+}
+
+// The web build can't read DIFFUSE_FILE, so it has a copy built in
+#[cfg(target_arch = "wasm32")]
+fn built_in_diffuse() -> anyhow::Result<image::RgbaImage> {
+    let image = image::load_from_memory(include_bytes!("happy-tree.png"))
+        .context("Couldn't decode the built-in diffuse image")?;
+    Ok(image.to_rgba8())
+}
+
+// Scene files are found next to Cargo.toml rather than wherever it's run from
+fn asset_path(file: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join(file)
+}
+
+#[cfg(feature = "hot-reload")]
+fn sky_files() -> impl Iterator<Item = PathBuf> {
+    std::iter::once(SKY_EQUIRECT_FILE).chain(SKY_FACE_FILES).map(asset_path)
+}
+
+// Shaders under src/, and the directories of every texture and sky file.
+// Textures are all requested by the time this runs. A directory that doesn't
+// exist yet, like sky/ before there's a sky, can't be watched and is skipped
+#[cfg(feature = "hot-reload")]
+fn watch_assets(textures: &TextureManager) -> Option<AssetWatcher> {
+    let mut assets = AssetWatcher::new().map_err(|e| log::error!("{:#}", e)).ok()?;
+    let directories = std::iter::once((PathBuf::from(hot_reload::SOURCE_DIR), true))
+        .chain(textures.directories().into_iter().map(|directory| (directory, false)))
+        .chain(sky_files().filter_map(|file| file.parent().map(|directory| (directory.to_path_buf(), false))));
+    for (directory, recursive) in directories {
+        if !directory.is_dir() {
+            continue;
+        }
+        if let Err(e) = assets.watch(&directory, recursive) {
+            log::error!("{:#}", e);
+        }
+    }
+    Some(assets)
+}
+
+// Number keys 1-9 name bookmark slots "1" to "9". 0 is the start view, so
+// Ctrl+0 changes where the next run begins
+fn bookmark_slot(keycode: KeyCode) -> Option<&'static str> {
+    match keycode {
+        KeyCode::Digit0 => Some(START_BOOKMARK),
+        KeyCode::Digit1 => Some("1"),
+        KeyCode::Digit2 => Some("2"),
+        KeyCode::Digit3 => Some("3"),
+        KeyCode::Digit4 => Some("4"),
+        KeyCode::Digit5 => Some("5"),
+        KeyCode::Digit6 => Some("6"),
+        KeyCode::Digit7 => Some("7"),
+        KeyCode::Digit8 => Some("8"),
+        KeyCode::Digit9 => Some("9"),
+        _ => None,
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 enum RenderState {
     Standard,
+    TextureArray,
     PositionColor,
 }
 
+impl RenderState {
+    const ALL: [RenderState; 3] = [RenderState::Standard, RenderState::TextureArray, RenderState::PositionColor];
+
+    fn shader_label(self) -> &'static str {
+        match self {
+            RenderState::Standard => "Standard Shader",
+            RenderState::TextureArray => "Texture Array Shader",
+            RenderState::PositionColor => "Position Color Shader",
+        }
+    }
+
+    fn shader_source(self) -> &'static str {
+        match self {
+            RenderState::Standard => concat!(include_str!("camera.wgsl"), include_str!("model.wgsl"), include_str!("standard_shader.wgsl")),
+            RenderState::TextureArray => concat!(include_str!("camera.wgsl"), include_str!("model.wgsl"), include_str!("texture_array_shader.wgsl")),
+            RenderState::PositionColor => concat!(include_str!("camera.wgsl"), include_str!("model.wgsl"), include_str!("position_color_shader.wgsl")),
+        }
+    }
+
+    // The files `shader_source` is made from, in the same order
+    #[cfg(feature = "hot-reload")]
+    fn shader_files(self) -> [&'static str; 3] {
+        match self {
+            RenderState::Standard => ["camera.wgsl", "model.wgsl", "standard_shader.wgsl"],
+            RenderState::TextureArray => ["camera.wgsl", "model.wgsl", "texture_array_shader.wgsl"],
+            RenderState::PositionColor => ["camera.wgsl", "model.wgsl", "position_color_shader.wgsl"],
+        }
+    }
+
+    fn pipeline_label(self) -> &'static str {
+        match self {
+            RenderState::Standard => "Standard Render Pipeline",
+            RenderState::TextureArray => "Texture Array Render Pipeline",
+            RenderState::PositionColor => "Position Color Render Pipeline",
+        }
+    }
+
+    fn vertex_buffers(self) -> Vec<wgpu::VertexBufferLayout<'static>> {
+        match self {
+            RenderState::Standard => vec![Vertex::desc()],
+            RenderState::TextureArray => vec![Vertex::desc(), LayerIndex::desc(wgpu::VertexStepMode::Vertex)],
+            RenderState::PositionColor => vec![],
+        }
+    }
+}
+
+// Depth test applied by every render pipeline
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct DepthConfig {
+    compare: wgpu::CompareFunction,
+    write_enabled: bool,
+}
+
+impl DepthConfig {
+    fn for_mode(depth_mode: DepthMode) -> Self {
+        Self {
+            compare: depth_mode.compare_function(),
+            write_enabled: true,
+        }
+    }
+
+    // Without writes everything is tested against the depth cleared to, so
+    // overlapping shapes draw in submission order
+    fn with_write(mut self, write_enabled: bool) -> Self {
+        self.write_enabled = write_enabled;
+        self
+    }
+}
+
 struct RenderPipelineState {
     state: RenderState,
-    standard: wgpu::RenderPipeline,
-    position_color: wgpu::RenderPipeline,
+    layout: wgpu::PipelineLayout,
+    // Binds a D2Array texture in place of the D2 one
+    array_layout: wgpu::PipelineLayout,
+    color_format: wgpu::TextureFormat,
+    depth: DepthConfig,
+    // Both indexed by `RenderState as usize`
+    shaders: [wgpu::ShaderModule; RenderState::ALL.len()],
+    pipelines: [wgpu::RenderPipeline; RenderState::ALL.len()],
 }
 
 impl RenderPipelineState {
-    fn new(standard: wgpu::RenderPipeline, position_color: wgpu::RenderPipeline) -> Self {
+    fn new(
+        device: &wgpu::Device,
+        layout: wgpu::PipelineLayout,
+        array_layout: wgpu::PipelineLayout,
+        color_format: wgpu::TextureFormat,
+        depth: DepthConfig,
+    ) -> Self {
+        let shaders = RenderState::ALL.map(|state| device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some(state.shader_label()),
+            source: wgpu::ShaderSource::Wgsl(state.shader_source().into()),
+        }));
+        let pipelines = RenderState::ALL.map(|state| {
+            let layout = match state {
+                RenderState::TextureArray => &array_layout,
+                RenderState::Standard | RenderState::PositionColor => &layout,
+            };
+            create_render_pipeline(device, state.pipeline_label(), layout, &shaders[state as usize], &state.vertex_buffers(), color_format, depth)
+        });
+
         Self {
             state: RenderState::Standard,
-            standard,
-            position_color,
+            layout,
+            array_layout,
+            color_format,
+            depth,
+            shaders,
+            pipelines,
         }
     }
 
-    fn pipeline(&self) -> &wgpu::RenderPipeline {
-        match self.state {
-            RenderState::Standard => {
-                &self.standard
-            },
-            RenderState::PositionColor => {
-                &self.position_color
-            },
+    fn create_pipeline(&self, device: &wgpu::Device, state: RenderState, shader: &wgpu::ShaderModule) -> wgpu::RenderPipeline {
+        let layout = match state {
+            RenderState::TextureArray => &self.array_layout,
+            RenderState::Standard | RenderState::PositionColor => &self.layout,
+        };
+        create_render_pipeline(device, state.pipeline_label(), layout, shader, &state.vertex_buffers(), self.color_format, self.depth)
+    }
+
+    fn set_depth(&mut self, device: &wgpu::Device, depth: DepthConfig) {
+        if depth == self.depth {
+            return;
         }
+        self.depth = depth;
+        self.pipelines = RenderState::ALL.map(|state| self.create_pipeline(device, state, &self.shaders[state as usize]));
+    }
+
+    // Rebuilds the pipelines whose shaders use any of `changed`. One that
+    // fails to compile logs why and keeps its last working pipeline
+    #[cfg(feature = "hot-reload")]
+    fn reload_shaders(&mut self, device: &wgpu::Device, changed: &[PathBuf]) {
+        for state in RenderState::ALL {
+            let files = state.shader_files();
+            if !changed.iter().any(|path| hot_reload::is_any_of(path, &files)) {
+                continue;
+            }
+            let reloaded = hot_reload::read_shader(&files).and_then(|source| hot_reload::checked(device, || {
+                let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+                    label: Some(state.shader_label()),
+                    source: wgpu::ShaderSource::Wgsl(source.into()),
+                });
+                let pipeline = self.create_pipeline(device, state, &shader);
+                (shader, pipeline)
+            }));
+            match reloaded {
+                Ok((shader, pipeline)) => {
+                    self.shaders[state as usize] = shader;
+                    self.pipelines[state as usize] = pipeline;
+                    log::info!("Reloaded {}", state.shader_label());
+                },
+                Err(e) => log::error!("Keeping the previous {}: {:#}", state.shader_label(), e),
+            }
+        }
+    }
+
+    fn pipeline(&self) -> &wgpu::RenderPipeline {
+        &self.pipelines[self.state as usize]
     }
 
     fn next(&self) -> RenderState {
         match self.state {
-            RenderState::Standard => RenderState::PositionColor,
+            RenderState::Standard => RenderState::TextureArray,
+            RenderState::TextureArray => RenderState::PositionColor,
             RenderState::PositionColor => RenderState::Standard,
         }
     }
 }
 
+fn create_render_pipeline(
+    device: &wgpu::Device,
+    label: &str,
+    layout: &wgpu::PipelineLayout,
+    shader: &wgpu::ShaderModule,
+    buffers: &[wgpu::VertexBufferLayout],
+    color_format: wgpu::TextureFormat,
+    depth: DepthConfig,
+) -> wgpu::RenderPipeline {
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some(label),
+        layout: Some(layout),
+        vertex: wgpu::VertexState {
+            module: shader,
+            entry_point: "vs_main",
+            buffers,
+            compilation_options: wgpu::PipelineCompilationOptions::default(),
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: shader,
+            entry_point: "fs_main",
+            targets: &[Some(wgpu::ColorTargetState {
+                format: color_format,
+                blend: Some(wgpu::BlendState::REPLACE),
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+            compilation_options: wgpu::PipelineCompilationOptions::default(),
+        }),
+        primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            strip_index_format: None,
+            front_face: wgpu::FrontFace::Ccw,
+            cull_mode: Some(wgpu::Face::Back),
+            unclipped_depth: false,
+            polygon_mode: wgpu::PolygonMode::Fill,
+            conservative: false,
+        },
+        depth_stencil: Some(wgpu::DepthStencilState {
+            format: texture::Texture::DEPTH_FORMAT,
+            depth_write_enabled: depth.write_enabled,
+            depth_compare: depth.compare,
+            stencil: wgpu::StencilState::default(),
+            bias: wgpu::DepthBiasState::default(),
+        }),
+        multisample: wgpu::MultisampleState {
+            count: 1,
+            mask: !0,
+            alpha_to_coverage_enabled: false,
+        },
+        multiview: None,
+        cache: None,
+    })
+}
+
 
 #[repr(C)]
 #[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
@@ -453,6 +1339,34 @@ impl Vertex {
     }
 }
 
+// Which layer of a texture array to sample, read per vertex or per instance
+// depending on the step mode it's bound with
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct LayerIndex {
+    layer: u32,
+}
+
+impl LayerIndex {
+    fn desc(step_mode: wgpu::VertexStepMode) -> wgpu::VertexBufferLayout::<'static> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<LayerIndex>() as wgpu::BufferAddress,
+            step_mode,
+            attributes: &[
+                wgpu::VertexAttribute {
+                    offset: 0,
+                    shader_location: 2,
+                    format: wgpu::VertexFormat::Uint32,
+                },
+            ],
+        }
+    }
+}
+
+fn atlas_vertices(uv: UvRect) -> Vec<Vertex> {
+    VERTICES.iter().map(|v| Vertex { tex_coords: uv.map(v.tex_coords), ..*v }).collect()
+}
+
 const VERTICES: &[Vertex] = &[
     Vertex { position: [-0.0868241, 0.49240386, 0.0], tex_coords: [0.4131759, 0.00759614] },
     Vertex { position: [-0.49513406, 0.06958647, 0.0], tex_coords: [0.0048659444, 0.43041354] },
@@ -469,6 +1383,25 @@ const VERTICES: &[Vertex] = &[
     Vertex { position: [0.0, 0.0, 0.0], tex_coords: [0.1, 0.1] },
 ];
 
+// One per vertex in VERTICES, giving each shape its own layer of LAYER_FILES
+const VERTEX_LAYERS: &[LayerIndex] = &[
+    // Pentagon
+    LayerIndex { layer: 0 },
+    LayerIndex { layer: 0 },
+    LayerIndex { layer: 0 },
+    LayerIndex { layer: 0 },
+    LayerIndex { layer: 0 },
+    // Arrow
+    LayerIndex { layer: 1 },
+    LayerIndex { layer: 1 },
+    LayerIndex { layer: 1 },
+    LayerIndex { layer: 1 },
+    LayerIndex { layer: 1 },
+    LayerIndex { layer: 1 },
+    LayerIndex { layer: 1 },
+    LayerIndex { layer: 1 },
+];
+
 const INDICES: &[u16] = &[
     // Pentagon, 3 triangles
     0, 1, 4,
@@ -485,20 +1418,40 @@ const INDICES: &[u16] = &[
     10, 11, 12,
 ];
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 enum Shapes {
     Pentagon,
     Arrow,
 }
 
+impl Shapes {
+    const ALL: [Shapes; 2] = [Shapes::Pentagon, Shapes::Arrow];
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct ModelUniform {
+    model: [[f32; 4]; 4],
+}
+
 struct ShapeState {
     state: Shapes,
     vertex_buffer: wgpu::Buffer,
+    // The same vertices with texture coordinates inside the atlas, for the
+    // standard pipeline
+    atlas_vertex_buffer: wgpu::Buffer,
+    layer_buffer: wgpu::Buffer,
     index_buffer: wgpu::Buffer,
+    // Indexed by `Shapes as usize`, each shape spins about its own center
+    rotations: [cgmath::Quaternion<f32>; Shapes::ALL.len()],
+    model_uniform_stride: wgpu::BufferAddress,
+    model_buffer: wgpu::Buffer,
+    model_bind_group: wgpu::BindGroup,
 }
 
 impl ShapeState {
-    fn new(device: &wgpu::Device) -> Self {
+    // `uv` is where the diffuse image sits in the atlas
+    fn new(device: &wgpu::Device, model_bind_group_layout: &wgpu::BindGroupLayout, uv: UvRect) -> Self {
         let vertex_buffer = device.create_buffer_init(
             &wgpu::util::BufferInitDescriptor {
                 label: Some("Pentagon Vertex Buffer"),
@@ -506,6 +1459,20 @@ impl ShapeState {
                 usage: wgpu::BufferUsages::VERTEX,
             }
         );
+        let atlas_vertex_buffer = device.create_buffer_init(
+            &wgpu::util::BufferInitDescriptor {
+                label: Some("Pentagon Atlas Vertex Buffer"),
+                contents: bytemuck::cast_slice(&atlas_vertices(uv)),
+                usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            }
+        );
+        let layer_buffer = device.create_buffer_init(
+            &wgpu::util::BufferInitDescriptor {
+                label: Some("Pentagon Layer Buffer"),
+                contents: bytemuck::cast_slice(VERTEX_LAYERS),
+                usage: wgpu::BufferUsages::VERTEX,
+            }
+        );
         let index_buffer = device.create_buffer_init(
             &wgpu::util::BufferInitDescriptor {
                 label: Some("Pentagon Index Buffer"),
@@ -514,19 +1481,101 @@ impl ShapeState {
             }
         );
 
+        let model_uniform_size = std::mem::size_of::<ModelUniform>() as wgpu::BufferAddress;
+        let model_uniform_stride = wgpu::util::align_to(
+            model_uniform_size,
+            device.limits().min_uniform_buffer_offset_alignment as wgpu::BufferAddress,
+        );
+        let model_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Model Buffer"),
+            size: model_uniform_stride * Shapes::ALL.len() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let model_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("model_bind_group"),
+            layout: model_bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                        buffer: &model_buffer,
+                        offset: 0,
+                        size: wgpu::BufferSize::new(model_uniform_size),
+                    }),
+                },
+            ],
+        });
+
         Self {
             state: Shapes::Pentagon,
             vertex_buffer,
+            atlas_vertex_buffer,
+            layer_buffer,
             index_buffer,
+            rotations: [cgmath::Quaternion::new(1.0, 0.0, 0.0, 0.0); Shapes::ALL.len()],
+            model_uniform_stride,
+            model_buffer,
+            model_bind_group,
+        }
+    }
+
+    fn model_matrix(&self, shape: Shapes) -> cgmath::Matrix4<f32> {
+        use cgmath::EuclideanSpace;
+        let center = self.local_bounds_of(shape).center().to_vec();
+        cgmath::Matrix4::from_translation(center)
+            * cgmath::Matrix4::from(self.rotations[shape as usize])
+            * cgmath::Matrix4::from_translation(-center)
+    }
+
+    fn model_offset(&self) -> wgpu::DynamicOffset {
+        (self.state as usize as wgpu::BufferAddress * self.model_uniform_stride) as wgpu::DynamicOffset
+    }
+
+    fn write_models(&self, queue: &wgpu::Queue) {
+        for shape in Shapes::ALL {
+            let uniform = ModelUniform {
+                model: self.model_matrix(shape).into(),
+            };
+            queue.write_buffer(
+                &self.model_buffer,
+                shape as usize as wgpu::BufferAddress * self.model_uniform_stride,
+                bytemuck::cast_slice(&[uniform]),
+            );
+        }
+    }
+
+    fn rotate(&mut self, shape: Shapes, rotation: cgmath::Quaternion<f32>) {
+        use cgmath::InnerSpace;
+        let current = &mut self.rotations[shape as usize];
+        *current = (rotation * *current).normalize();
+    }
+
+    fn reset_rotations(&mut self) {
+        self.rotations = [cgmath::Quaternion::new(1.0, 0.0, 0.0, 0.0); Shapes::ALL.len()];
+    }
+
+    fn vertex_buffer_slice(&self, state: RenderState) -> BufferSlice<'_> {
+        match state {
+            RenderState::Standard => self.atlas_vertex_buffer.slice(..),
+            RenderState::TextureArray | RenderState::PositionColor => self.vertex_buffer.slice(..),
         }
     }
 
-    fn vertex_buffer_slice(&self) -> BufferSlice {
-        self.vertex_buffer.slice(..)
+    fn set_atlas_uv(&self, queue: &wgpu::Queue, uv: UvRect) {
+        queue.write_buffer(&self.atlas_vertex_buffer, 0, bytemuck::cast_slice(&atlas_vertices(uv)));
+    }
+
+    fn layer_buffer_slice(&self) -> BufferSlice<'_> {
+        self.layer_buffer.slice(..)
     }
 
     fn index_buffer_indices(&self) -> Range<u32> {
-        match self.state {
+        Self::indices_of(self.state)
+    }
+
+    fn indices_of(shape: Shapes) -> Range<u32> {
+        match shape {
             Shapes::Pentagon => {
                 0..9
             },
@@ -536,14 +1585,59 @@ impl ShapeState {
         }
     }
 
-    fn index_buffer_slice(&self) -> BufferSlice {
+    fn index_buffer_slice(&self) -> BufferSlice<'_> {
         self.index_buffer.slice(..)
     }
 
+    fn bounds(&self) -> Aabb {
+        self.bounds_of(self.state)
+    }
+
+    // World space
+    fn bounds_of(&self, shape: Shapes) -> Aabb {
+        self.local_bounds_of(shape).transformed(&self.model_matrix(shape))
+    }
+
+    fn local_bounds_of(&self, shape: Shapes) -> Aabb {
+        Aabb::from_points(self.triangles_of(shape).flatten()).expect("shapes aren't empty")
+    }
+
     fn base_vertex(&self) -> i32 {
         0
     }
 
+    fn triangles(&self) -> impl Iterator<Item = [cgmath::Point3<f32>; 3]> + '_ {
+        self.triangles_of(self.state)
+    }
+
+    fn triangles_of(&self, shape: Shapes) -> impl Iterator<Item = [cgmath::Point3<f32>; 3]> + '_ {
+        let range = Self::indices_of(shape);
+        let base_vertex = self.base_vertex() as usize;
+        INDICES[range.start as usize..range.end as usize]
+            .chunks_exact(3)
+            .map(move |t| [t[0], t[1], t[2]].map(|i| VERTICES[base_vertex + i as usize].position.into()))
+    }
+
+    // Only the visible shape can be hit
+    fn pick(&self, ray: &Ray) -> Option<PickHit> {
+        use cgmath::SquareMatrix;
+        ray.intersect_aabb(&self.bounds())?;
+        // model matrices are rigid, so distances carry over from model space
+        let local_ray = ray.transformed(&self.model_matrix(self.state).invert()?);
+        self.triangles()
+            .enumerate()
+            .filter_map(|(triangle, points)| {
+                local_ray.intersect_triangle(points).map(|distance| (triangle, distance))
+            })
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+            .map(|(triangle, distance)| PickHit {
+                shape: self.state,
+                triangle,
+                distance,
+                point: ray.at(distance),
+            })
+    }
+
     fn swap(&mut self) {
         match self.state {
             Shapes::Pentagon => {
@@ -555,3 +1649,11 @@ impl ShapeState {
         }
     }
 }
+
+#[derive(Debug, Clone, Copy)]
+struct PickHit {
+    shape: Shapes,
+    triangle: usize,
+    distance: f32,
+    point: cgmath::Point3<f32>,
+}
@@ -0,0 +1,86 @@
+// Helpers for tests that compare GPU output with the CPU reference paths
+
+use crate::texture;
+
+// None when there's no adapter to run on, in which case the GPU half of a
+// test is skipped. Asks for the same optional features as the app does
+pub fn device() -> Option<(wgpu::Device, wgpu::Queue)> {
+    let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+    runtime.block_on(async {
+        let instance = wgpu::Instance::default();
+        let Some(adapter) = instance.request_adapter(&wgpu::RequestAdapterOptions::default()).await else {
+            eprintln!("No adapter, skipping the GPU comparison");
+            return None;
+        };
+        adapter
+            .request_device(
+                &wgpu::DeviceDescriptor {
+                    label: Some("Test Device"),
+                    required_features: adapter.features() & texture::OPTIONAL_FEATURES,
+                    required_limits: adapter.limits(),
+                    memory_hints: wgpu::MemoryHints::default(),
+                },
+                None,
+            )
+            .await
+            .ok()
+    })
+}
+
+// Mip `level` of layer 0 of an Rgba8 texture with COPY_SRC usage, alpha included
+pub fn read_level(device: &wgpu::Device, queue: &wgpu::Queue, texture: &wgpu::Texture, level: u32) -> image::RgbaImage {
+    let width = (texture.width() >> level).max(1);
+    let height = (texture.height() >> level).max(1);
+    image::RgbaImage::from_raw(width, height, read_bytes(device, queue, texture, level)).unwrap()
+}
+
+// The texels of mip `level` of layer 0 as stored, for any uncompressed format
+pub fn read_bytes(device: &wgpu::Device, queue: &wgpu::Queue, texture: &wgpu::Texture, level: u32) -> Vec<u8> {
+    let width = (texture.width() >> level).max(1);
+    let height = (texture.height() >> level).max(1);
+    let bytes_per_pixel = texture.format().block_copy_size(None).unwrap();
+    let padded_bytes_per_row = wgpu::util::align_to(width * bytes_per_pixel, wgpu::COPY_BYTES_PER_ROW_ALIGNMENT);
+    let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Test Readback Buffer"),
+        size: padded_bytes_per_row as wgpu::BufferAddress * height as wgpu::BufferAddress,
+        usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+        mapped_at_creation: false,
+    });
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+    encoder.copy_texture_to_buffer(
+        wgpu::ImageCopyTexture {
+            texture,
+            mip_level: level,
+            origin: wgpu::Origin3d::ZERO,
+            aspect: wgpu::TextureAspect::All,
+        },
+        wgpu::ImageCopyBuffer {
+            buffer: &buffer,
+            layout: wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(padded_bytes_per_row),
+                rows_per_image: Some(height),
+            },
+        },
+        wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        },
+    );
+    queue.submit(std::iter::once(encoder.finish()));
+
+    let slice = buffer.slice(..);
+    slice.map_async(wgpu::MapMode::Read, |result| result.unwrap());
+    device.poll(wgpu::Maintain::Wait);
+    let data = slice.get_mapped_range();
+    data.chunks(padded_bytes_per_row as usize)
+        .flat_map(|row| row[..(width * bytes_per_pixel) as usize].to_vec())
+        .collect()
+}
+
+// Largest difference between any two channels
+pub fn max_difference(a: &image::RgbaImage, b: &image::RgbaImage) -> u8 {
+    assert_eq!(a.dimensions(), b.dimensions());
+    a.as_raw().iter().zip(b.as_raw()).map(|(a, b)| a.abs_diff(*b)).max().unwrap_or(0)
+}
@@ -1,80 +1,712 @@
+use std::sync::Arc;
+
 use image::GenericImageView;
 use anyhow::*;
 
+use crate::{
+    color,
+    compressed::{self, CompressedImage},
+    cubemap::{self, CUBE_FACES},
+    mipmap,
+    procedural::{self, Pattern},
+    sampler::{SamplerCache, SamplerOptions},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Mipmaps {
+    // Only the base level
+    None,
+    // Rendered from the base level on the GPU after upload
+    Gpu,
+    // Downsampled on the CPU and uploaded level by level
+    #[allow(unused)]
+    Cpu,
+}
+
+// How 8- and 16-bit colour values are interpreted. Colour maps are sRGB;
+// data such as normal or roughness maps must be sampled as stored
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ColorSpace {
+    Srgb,
+    #[allow(unused)]
+    Linear,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TextureOptions {
+    sampler: SamplerOptions,
+    color_space: ColorSpace,
+    usage: wgpu::TextureUsages,
+    mipmaps: Mipmaps,
+    max_mip_levels: u32,
+}
+
+impl Default for TextureOptions {
+    fn default() -> Self {
+        Self {
+            sampler: SamplerOptions::default(),
+            color_space: ColorSpace::Srgb,
+            usage: wgpu::TextureUsages::empty(),
+            mipmaps: Mipmaps::Gpu,
+            max_mip_levels: u32::MAX,
+        }
+    }
+}
+
+impl TextureOptions {
+    #[allow(unused)]
+    pub fn with_address_mode(mut self, address_mode: wgpu::AddressMode) -> Self {
+        self.sampler.address_mode_u = address_mode;
+        self.sampler.address_mode_v = address_mode;
+        self.sampler.address_mode_w = address_mode;
+        self
+    }
+
+    pub fn with_filters(mut self, mag: wgpu::FilterMode, min: wgpu::FilterMode, mipmap: wgpu::FilterMode) -> Self {
+        self.sampler.mag_filter = mag;
+        self.sampler.min_filter = min;
+        self.sampler.mipmap_filter = mipmap;
+        self
+    }
+
+    // Switches all three filters to Linear too, which anisotropy needs
+    #[allow(unused)]
+    pub fn with_anisotropy(mut self, clamp: u16) -> Self {
+        self.sampler.anisotropy_clamp = clamp;
+        self.with_filters(wgpu::FilterMode::Linear, wgpu::FilterMode::Linear, wgpu::FilterMode::Linear)
+    }
+
+    pub fn with_color_space(mut self, color_space: ColorSpace) -> Self {
+        self.color_space = color_space;
+        self
+    }
+
+    // Added to TEXTURE_BINDING | COPY_DST. sRGB formats can't be used with
+    // STORAGE_BINDING, so storage textures need ColorSpace::Linear
+    pub fn with_usage(mut self, usage: wgpu::TextureUsages) -> Self {
+        self.usage |= usage;
+        self
+    }
+
+    pub fn with_mipmaps(mut self, mipmaps: Mipmaps) -> Self {
+        self.mipmaps = mipmaps;
+        self
+    }
+
+    // Caps the chain, counting the base level
+    pub fn with_max_mip_levels(mut self, max_mip_levels: u32) -> Self {
+        self.max_mip_levels = max_mip_levels.max(1);
+        self
+    }
+
+    fn mip_level_count(&self, width: u32, height: u32) -> u32 {
+        match self.mipmaps {
+            Mipmaps::None => 1,
+            Mipmaps::Gpu | Mipmaps::Cpu => mipmap::mip_level_count(width, height).min(self.max_mip_levels),
+        }
+    }
+}
+
+// A file decoded on the CPU and ready to upload. Decoding is the slow part,
+// so it's kept apart from uploading to be done off the render thread
+pub enum DecodedImage {
+    Image(image::DynamicImage),
+    Compressed(CompressedImage),
+}
+
+impl DecodedImage {
+    pub fn decode(bytes: &[u8], options: &TextureOptions) -> Result<Self> {
+        if compressed::is_container(bytes) {
+            return Ok(Self::Compressed(CompressedImage::load(bytes, options.color_space)?));
+        }
+        Ok(Self::Image(image::load_from_memory(bytes)?))
+    }
+}
+
 pub struct Texture {
     #[allow(unused)]
     pub texture: wgpu::Texture,
     pub view: wgpu::TextureView,
-    pub sampler: wgpu::Sampler,
+    pub sampler: Arc<wgpu::Sampler>,
+    pub format: wgpu::TextureFormat,
 }
 
 impl Texture {
+    pub const DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
+
+    // Sized to the surface, so it has to be recreated whenever that resizes
+    pub fn create_depth_texture(
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+        compare: wgpu::CompareFunction,
+        label: &str,
+        samplers: &SamplerCache,
+    ) -> Self {
+        let size = wgpu::Extent3d {
+            width: config.width.max(1),
+            height: config.height.max(1),
+            depth_or_array_layers: 1,
+        };
+        let texture = device.create_texture(
+            &wgpu::TextureDescriptor {
+                label: Some(label),
+                size,
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: Self::DEPTH_FORMAT,
+                usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+                view_formats: &[],
+            },
+        );
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        // Comparison sampler for reading it back as a shadow-style texture,
+        // using the same `compare` as the depth test
+        let sampler = samplers.get(device, &SamplerOptions {
+            min_filter: wgpu::FilterMode::Linear,
+            compare: Some(compare),
+            ..Default::default()
+        });
+
+        Self {texture, view, sampler, format: Self::DEPTH_FORMAT}
+    }
+
+    #[allow(unused)]
     pub fn from_bytes(
         device: &wgpu::Device,
         queue: &wgpu::Queue,
         bytes: &[u8],
         label: &str,
+        options: &TextureOptions,
+        samplers: &SamplerCache,
     ) -> Result<Self> {
-        let img = image::load_from_memory(bytes)?;
-        Self::from_image(device, queue, &img, Some(label))
+        let decoded = DecodedImage::decode(bytes, options)?;
+        Self::from_decoded(device, queue, &decoded, Some(label), options, samplers)
     }
 
-    pub fn from_image(
+    pub fn from_decoded(
         device: &wgpu::Device,
         queue: &wgpu::Queue,
-        img: &image::DynamicImage,
+        decoded: &DecodedImage,
         label: Option<&str>,
+        options: &TextureOptions,
+        samplers: &SamplerCache,
     ) -> Result<Self> {
-        let rgba = img.to_rgba8();
-        let dimensions = img.dimensions();
+        match decoded {
+            DecodedImage::Image(img) => Self::from_image(device, queue, img, label, options, samplers),
+            DecodedImage::Compressed(image) => Self::from_compressed(device, queue, image, label, options, samplers),
+        }
+    }
 
-        let size = wgpu::Extent3d {
-            width: dimensions.0,
-            height: dimensions.1,
-            depth_or_array_layers: 1,
+    // Uploads the blocks as they are, with the mip levels from the file since
+    // block formats can't be rendered to. Formats the device can't sample are
+    // decompressed to RGBA8 first
+    pub fn from_compressed(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        image: &CompressedImage,
+        label: Option<&str>,
+        options: &TextureOptions,
+        samplers: &SamplerCache,
+    ) -> Result<Self> {
+        let decompressed;
+        let image = if device.features().contains(image.format.required_features()) {
+            image
+        } else {
+            log::warn!("{:?} isn't supported by this device, decompressing {} on the CPU", image.format, label.unwrap_or("texture"));
+            decompressed = image.decompress()?;
+            &decompressed
+        };
+
+        let mip_level_count = match options.mipmaps {
+            Mipmaps::None => 1,
+            Mipmaps::Gpu | Mipmaps::Cpu => (image.levels.len() as u32).min(options.max_mip_levels),
         };
         let texture = device.create_texture(
             &wgpu::TextureDescriptor {
                 label,
-                size,
-                mip_level_count: 1,
+                size: wgpu::Extent3d {
+                    width: image.width,
+                    height: image.height,
+                    depth_or_array_layers: image.layers,
+                },
+                mip_level_count,
                 sample_count: 1,
                 dimension: wgpu::TextureDimension::D2,
-                format: wgpu::TextureFormat::Rgba8UnormSrgb,
-                usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+                format: image.format,
+                usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST | options.usage,
                 view_formats: &[],
             },
         );
+        for level in 0..mip_level_count {
+            let (width, height) = image.level_size(level);
+            for layer in 0..image.layers {
+                write_level(queue, &texture, level, layer, width, height, image.layer(level, layer));
+            }
+        }
 
-        queue.write_texture(
-            wgpu::ImageCopyTexture {
-                aspect: wgpu::TextureAspect::All,
-                texture: &texture,
-                mip_level: 0,
-                origin: wgpu::Origin3d::ZERO,
+        if image.cube {
+            return Ok(Self::cube(device, texture, options, samplers));
+        }
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            dimension: Some(if image.layers > 1 { wgpu::TextureViewDimension::D2Array } else { wgpu::TextureViewDimension::D2 }),
+            ..Default::default()
+        });
+        let sampler = samplers.get(device, &options.sampler);
+        Ok(Self {texture, view, sampler, format: image.format})
+    }
+
+    // 8-bit images upload as Rgba8UnormSrgb. 16-bit images are decoded to
+    // linear and kept at 16 bits, and float images (.hdr, .exr) stay float;
+    // see `upload_format`
+    pub fn from_image(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        img: &image::DynamicImage,
+        label: Option<&str>,
+        options: &TextureOptions,
+        samplers: &SamplerCache,
+    ) -> Result<Self> {
+        let dimensions = img.dimensions();
+        let format = upload_format(device, img, options.color_space);
+        let options = &mipmaps_for_format(device, format, options);
+        let texture = create_texture(device, label, dimensions.0, dimensions.1, 1, format, options);
+        write_image(queue, &texture, 0, img, options);
+        if options.mipmaps == Mipmaps::Gpu {
+            mipmap::generate(device, queue, &texture);
+        }
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let sampler = samplers.get(device, &options.sampler);
+
+        Ok(Self {texture, view, sampler, format})
+    }
+
+    // Faces in wgpu's layer order: +X, -X, +Y, -Y, +Z, -Z. All six must be
+    // the same square size
+    pub fn cube_from_faces(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        faces: &[image::DynamicImage; 6],
+        label: Option<&str>,
+        options: &TextureOptions,
+        samplers: &SamplerCache,
+    ) -> Result<Self> {
+        let (width, height) = faces[0].dimensions();
+        if width != height {
+            bail!("Cubemap faces must be square, found {}x{}", width, height);
+        }
+        if let Some(face) = faces.iter().find(|face| face.dimensions() != (width, height)) {
+            bail!("Cubemap faces must all be {}x{}, found {}x{}", width, height, face.width(), face.height());
+        }
+
+        let format = upload_format(device, &faces[0], options.color_space);
+        let options = &mipmaps_for_format(device, format, options);
+        let texture = create_texture(device, label, width, height, 6, format, options);
+        for (layer, face) in faces.iter().enumerate() {
+            write_image(queue, &texture, layer as u32, face, options);
+        }
+        if options.mipmaps == Mipmaps::Gpu {
+            mipmap::generate(device, queue, &texture);
+        }
+
+        Ok(Self::cube(device, texture, options, samplers))
+    }
+
+    // Projects a latitude/longitude panorama, typically an .hdr, onto a cube
+    // with `face_size` pixel faces. The projection runs on the GPU
+    pub fn cube_from_equirect(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        img: &image::DynamicImage,
+        face_size: u32,
+        label: Option<&str>,
+        options: &TextureOptions,
+        samplers: &SamplerCache,
+    ) -> Result<Self> {
+        let equirect_options = options.with_mipmaps(Mipmaps::None);
+        let equirect = Self::from_image(device, queue, img, Some("equirect_source"), &equirect_options, samplers)?;
+
+        // Always rendered into, whatever the mip settings
+        let options = options.with_usage(wgpu::TextureUsages::RENDER_ATTACHMENT);
+        let options = if options.mipmaps == Mipmaps::Cpu { options.with_mipmaps(Mipmaps::Gpu) } else { options };
+        // Half floats are plenty for a sky and, unlike Rgba32Float or
+        // Rgba16Unorm, can always be rendered to
+        let format = match equirect.format {
+            wgpu::TextureFormat::Rgba32Float => wgpu::TextureFormat::Rgba16Float,
+            format if is_renderable(device, format) => format,
+            _ => wgpu::TextureFormat::Rgba16Float,
+        };
+        let texture = create_texture(device, label, face_size, face_size, 6, format, &options);
+        cubemap::project_equirect(device, queue, &equirect, &texture, samplers);
+        if options.mipmaps == Mipmaps::Gpu {
+            mipmap::generate(device, queue, &texture);
+        }
+
+        Ok(Self::cube(device, texture, &options, samplers))
+    }
+
+    // One layer per image, in order, bound as a D2Array. Images must all be
+    // the same size; ones larger than the device allows are scaled down
+    pub fn array_from_images(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        images: &[image::DynamicImage],
+        label: Option<&str>,
+        options: &TextureOptions,
+        samplers: &SamplerCache,
+    ) -> Result<Self> {
+        let Some(first) = images.first() else {
+            bail!("A texture array needs at least one image");
+        };
+        let (width, height) = first.dimensions();
+        if let Some((index, img)) = images.iter().enumerate().find(|(_, img)| img.dimensions() != (width, height)) {
+            bail!("Array layers must all be {}x{}, layer {} is {}x{}", width, height, index, img.width(), img.height());
+        }
+        let limits = device.limits();
+        let layers = array_layer_count(images.len() as u32, width, height);
+        if layers > limits.max_texture_array_layers {
+            bail!("{} array layers is more than the {} this device allows", layers, limits.max_texture_array_layers);
+        }
+
+        // WebGL2 only guarantees 2048 pixels a side
+        let max_size = limits.max_texture_dimension_2d;
+        let resized: Vec<image::DynamicImage>;
+        let images = if width > max_size || height > max_size {
+            log::warn!("Scaling {}x{} array layers down to fit in {}x{}", width, height, max_size, max_size);
+            resized = images
+                .iter()
+                .map(|img| img.resize(max_size, max_size, image::imageops::FilterType::Triangle))
+                .collect();
+            &resized[..]
+        } else {
+            images
+        };
+        let (width, height) = images[0].dimensions();
+
+        let format = upload_format(device, &images[0], options.color_space);
+        let options = &mipmaps_for_format(device, format, options);
+        let texture = create_texture(device, label, width, height, layers, format, options);
+        // Padding layers repeat the last image, so a layer index one past the
+        // images still shows it rather than black
+        for layer in 0..layers {
+            let img = &images[(layer as usize).min(images.len() - 1)];
+            write_image(queue, &texture, layer, img, options);
+        }
+        if options.mipmaps == Mipmaps::Gpu {
+            mipmap::generate(device, queue, &texture);
+        }
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            dimension: Some(wgpu::TextureViewDimension::D2Array),
+            ..Default::default()
+        });
+        let sampler = samplers.get(device, &options.sampler);
+        Ok(Self {texture, view, sampler, format})
+    }
+
+    // An Rgba8Unorm texture of `dimensions` filled with `pattern`, by a
+    // compute shader where the device has them and on the CPU otherwise.
+    // Storage textures can't be sRGB, so the colour space is always Linear
+    pub fn from_pattern(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        pattern: &Pattern,
+        dimensions: (u32, u32),
+        label: Option<&str>,
+        options: &TextureOptions,
+        samplers: &SamplerCache,
+    ) -> Result<Self> {
+        let (width, height) = dimensions;
+        let max_size = device.limits().max_texture_dimension_2d;
+        if width == 0 || height == 0 || width > max_size || height > max_size {
+            bail!("Can't make a {}x{} texture, sides must be 1 to {}", width, height, max_size);
+        }
+
+        let options = options.with_color_space(ColorSpace::Linear);
+        if !procedural::supports_compute(device) || options.mipmaps == Mipmaps::Cpu {
+            let img = image::DynamicImage::ImageRgba8(procedural::generate_cpu(pattern, width, height));
+            return Self::from_image(device, queue, &img, label, &options, samplers);
+        }
+
+        let options = options.with_usage(wgpu::TextureUsages::STORAGE_BINDING);
+        let format = wgpu::TextureFormat::Rgba8Unorm;
+        let texture = create_texture(device, label, width, height, 1, format, &options);
+        procedural::generate_gpu(device, queue, pattern, &texture);
+        if options.mipmaps == Mipmaps::Gpu {
+            mipmap::generate(device, queue, &texture);
+        }
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let sampler = samplers.get(device, &options.sampler);
+        Ok(Self {texture, view, sampler, format})
+    }
+
+    fn cube(device: &wgpu::Device, texture: wgpu::Texture, options: &TextureOptions, samplers: &SamplerCache) -> Self {
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            dimension: Some(wgpu::TextureViewDimension::Cube),
+            ..Default::default()
+        });
+        let sampler = samplers.get(device, &options.sampler);
+        let format = texture.format();
+        Self {texture, view, sampler, format}
+    }
+}
+
+// The texture at binding 0 and its sampler at binding 1, as the scene's
+// shaders expect. `dimension` is D2Array for `array_from_images` textures
+pub fn bind_group_layout(device: &wgpu::Device, dimension: wgpu::TextureViewDimension, label: &str) -> wgpu::BindGroupLayout {
+    device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        entries: &[
+            wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    view_dimension: dimension,
+                    multisampled: false,
+                },
+                count: None,
             },
-            &rgba,
-            wgpu::ImageDataLayout {
-                offset: 0,
-                bytes_per_row: Some(4 * dimensions.0),
-                rows_per_image: Some(dimensions.1),
+            wgpu::BindGroupLayoutEntry {
+                binding: 1,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                count: None,
             },
-            size,
-        );
+        ],
+        label: Some(label),
+    })
+}
 
-        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
-        let sampler = device.create_sampler(
-            &wgpu::SamplerDescriptor {
-                address_mode_u: wgpu::AddressMode::ClampToEdge,
-                address_mode_v: wgpu::AddressMode::ClampToEdge,
-                address_mode_w: wgpu::AddressMode::ClampToEdge,
-                mag_filter: wgpu::FilterMode::Linear,
-                min_filter: wgpu::FilterMode::Nearest,
-                mipmap_filter: wgpu::FilterMode::Nearest,
-                ..Default::default()
+// GL picks a texture's target from its shape when it's created: one layer
+// makes a plain 2D texture and six square layers a cubemap, neither of which
+// can be viewed as a D2Array. An unused layer is added to avoid both
+fn array_layer_count(images: u32, width: u32, height: u32) -> u32 {
+    if images == 1 || (images == CUBE_FACES && width == height) {
+        images + 1
+    } else {
+        images
+    }
+}
+
+fn create_texture(
+    device: &wgpu::Device,
+    label: Option<&str>,
+    width: u32,
+    height: u32,
+    layers: u32,
+    format: wgpu::TextureFormat,
+    options: &TextureOptions,
+) -> wgpu::Texture {
+    let mut usage = wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST | options.usage;
+    if options.mipmaps == Mipmaps::Gpu {
+        usage |= wgpu::TextureUsages::RENDER_ATTACHMENT;
+    }
+    device.create_texture(
+        &wgpu::TextureDescriptor {
+            label,
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: layers,
             },
-        );
+            mip_level_count: options.mip_level_count(width, height),
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage,
+            view_formats: &[],
+        },
+    )
+}
+
+// Whether the format can be rendered to without asking for
+// TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES, which isn't requested
+fn is_renderable(device: &wgpu::Device, format: wgpu::TextureFormat) -> bool {
+    format
+        .guaranteed_format_features(device.features())
+        .allowed_usages
+        .contains(wgpu::TextureUsages::RENDER_ATTACHMENT)
+}
+
+// The GPU mip pass renders into the texture, so formats that can't be
+// rendered to, such as Rgba16Unorm, have their mips made on the CPU instead
+fn mipmaps_for_format(device: &wgpu::Device, format: wgpu::TextureFormat, options: &TextureOptions) -> TextureOptions {
+    if options.mipmaps == Mipmaps::Gpu && !is_renderable(device, format) {
+        options.with_mipmaps(Mipmaps::Cpu)
+    } else {
+        *options
+    }
+}
+
+// Uploads `img` into `layer`, along with the lower mip levels when they're
+// made on the CPU
+fn write_image(queue: &wgpu::Queue, texture: &wgpu::Texture, layer: u32, img: &image::DynamicImage, options: &TextureOptions) {
+    let format = texture.format();
+    let levels_below = texture.mip_level_count() as usize - 1;
+    let cpu_mipmaps = options.mipmaps == Mipmaps::Cpu;
+    if matches!(format, wgpu::TextureFormat::Rgba8UnormSrgb | wgpu::TextureFormat::Rgba8Unorm) {
+        let rgba = img.to_rgba8();
+        write_level(queue, texture, 0, layer, rgba.width(), rgba.height(), &rgba);
+        if cpu_mipmaps {
+            for (level, image) in mipmap::cpu_mip_chain(&rgba, format.is_srgb()).iter().take(levels_below).enumerate() {
+                write_level(queue, texture, level as u32 + 1, layer, image.width(), image.height(), image);
+            }
+        }
+    } else {
+        let linear = to_linear(img, options.color_space);
+        write_level(queue, texture, 0, layer, linear.width(), linear.height(), &encode(format, &linear));
+        if cpu_mipmaps {
+            for (level, image) in mipmap::cpu_mip_chain_linear(&linear).iter().take(levels_below).enumerate() {
+                write_level(queue, texture, level as u32 + 1, layer, image.width(), image.height(), &encode(format, image));
+            }
+        }
+    }
+}
+
+// Features that let `upload_format` keep more precision, and compressed
+// textures stay compressed, requested when the adapter has them
+pub const OPTIONAL_FEATURES: wgpu::Features = wgpu::Features::TEXTURE_FORMAT_16BIT_NORM
+    .union(wgpu::Features::FLOAT32_FILTERABLE)
+    .union(wgpu::Features::TEXTURE_COMPRESSION_BC)
+    .union(wgpu::Features::TEXTURE_COMPRESSION_ETC2)
+    .union(wgpu::Features::TEXTURE_COMPRESSION_ASTC);
+
+// Every format here must stay filterable, since textures are bound with
+// `filterable: true`. Without FLOAT32_FILTERABLE float images drop to half
+// precision, and without TEXTURE_FORMAT_16BIT_NORM 16-bit images do too
+fn upload_format(device: &wgpu::Device, img: &image::DynamicImage, color_space: ColorSpace) -> wgpu::TextureFormat {
+    use image::DynamicImage::*;
+    let features = device.features();
+    match img {
+        ImageRgb32F(_) | ImageRgba32F(_) if features.contains(wgpu::Features::FLOAT32_FILTERABLE) => {
+            wgpu::TextureFormat::Rgba32Float
+        },
+        ImageRgb32F(_) | ImageRgba32F(_) => wgpu::TextureFormat::Rgba16Float,
+        ImageLuma16(_) | ImageLumaA16(_) | ImageRgb16(_) | ImageRgba16(_)
+            if features.contains(wgpu::Features::TEXTURE_FORMAT_16BIT_NORM) =>
+        {
+            wgpu::TextureFormat::Rgba16Unorm
+        },
+        ImageLuma16(_) | ImageLumaA16(_) | ImageRgb16(_) | ImageRgba16(_) => wgpu::TextureFormat::Rgba16Float,
+        _ if color_space == ColorSpace::Linear => wgpu::TextureFormat::Rgba8Unorm,
+        _ => wgpu::TextureFormat::Rgba8UnormSrgb,
+    }
+}
+
+// Float images are linear already. 16-bit sRGB images have no 16-bit sRGB
+// format to decode them on sampling, so that happens here
+fn to_linear(img: &image::DynamicImage, color_space: ColorSpace) -> image::Rgba32FImage {
+    let mut linear = img.to_rgba32f();
+    let is_float = matches!(img, image::DynamicImage::ImageRgb32F(_) | image::DynamicImage::ImageRgba32F(_));
+    if !is_float && color_space == ColorSpace::Srgb {
+        for pixel in linear.pixels_mut() {
+            for channel in &mut pixel.0[..3] {
+                *channel = color::srgb_to_linear(*channel);
+            }
+        }
+    }
+    linear
+}
+
+fn encode(format: wgpu::TextureFormat, image: &image::Rgba32FImage) -> Vec<u8> {
+    let values = image.as_raw().iter();
+    match format {
+        wgpu::TextureFormat::Rgba32Float => bytemuck::cast_slice(image.as_raw()).to_vec(),
+        wgpu::TextureFormat::Rgba16Float => values
+            .flat_map(|v| half::f16::from_f32(*v).to_bits().to_ne_bytes())
+            .collect(),
+        wgpu::TextureFormat::Rgba16Unorm => values
+            .flat_map(|v| ((v.clamp(0.0, 1.0) * 65535.0).round() as u16).to_ne_bytes())
+            .collect(),
+        _ => unreachable!("{:?} isn't a linear upload format", format),
+    }
+}
+
+// Rows are counted in blocks, which for uncompressed formats are single
+// texels. Levels smaller than a block still upload one whole block
+fn write_level(queue: &wgpu::Queue, texture: &wgpu::Texture, mip_level: u32, layer: u32, width: u32, height: u32, bytes: &[u8]) {
+    let format = texture.format();
+    let (block_width, block_height) = format.block_dimensions();
+    let block_size = format.block_copy_size(None)
+        .expect("uploaded formats are colour formats");
+    let (blocks_wide, blocks_high) = (width.div_ceil(block_width), height.div_ceil(block_height));
+    queue.write_texture(
+        wgpu::ImageCopyTexture {
+            aspect: wgpu::TextureAspect::All,
+            texture,
+            mip_level,
+            origin: wgpu::Origin3d { x: 0, y: 0, z: layer },
+        },
+        bytes,
+        wgpu::ImageDataLayout {
+            offset: 0,
+            bytes_per_row: Some(block_size * blocks_wide),
+            rows_per_image: Some(blocks_high),
+        },
+        wgpu::Extent3d {
+            width: blocks_wide * block_width,
+            height: blocks_high * block_height,
+            depth_or_array_layers: 1,
+        },
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    // Reads back texels of either 16-bit format as linear floats
+    fn decode_16(format: wgpu::TextureFormat, bytes: &[u8]) -> Vec<f32> {
+        bytes
+            .chunks_exact(2)
+            .map(|bits| {
+                let bits = u16::from_ne_bytes([bits[0], bits[1]]);
+                match format {
+                    wgpu::TextureFormat::Rgba16Unorm => bits as f32 / 65535.0,
+                    wgpu::TextureFormat::Rgba16Float => half::f16::from_bits(bits).to_f32(),
+                    _ => unreachable!("{:?} isn't a 16-bit upload format", format),
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn sixteen_bit_images_get_a_mip_chain() {
+        let Some((device, queue)) = testing::device() else {
+            return;
+        };
+        // Rgba16Unorm is only used with TEXTURE_FORMAT_16BIT_NORM, which not
+        // every adapter has, so its fallback is checked on its own too
+        let unorm = mipmaps_for_format(&device, wgpu::TextureFormat::Rgba16Unorm, &TextureOptions::default());
+        assert_eq!(unorm.mipmaps, Mipmaps::Cpu);
+        let float = mipmaps_for_format(&device, wgpu::TextureFormat::Rgba16Float, &TextureOptions::default());
+        assert_eq!(float.mipmaps, Mipmaps::Gpu);
+
+        let img = image::DynamicImage::ImageRgba16(image::ImageBuffer::from_fn(13, 10, |x, y| {
+            image::Rgba([x as u16 * 5000, y as u16 * 7000, 40000, 65535])
+        }));
+        let options = TextureOptions::default().with_usage(wgpu::TextureUsages::COPY_SRC);
+        let texture = Texture::from_image(&device, &queue, &img, Some("16-bit"), &options, &SamplerCache::new()).unwrap();
+        let expected_format = if device.features().contains(wgpu::Features::TEXTURE_FORMAT_16BIT_NORM) {
+            wgpu::TextureFormat::Rgba16Unorm
+        } else {
+            wgpu::TextureFormat::Rgba16Float
+        };
+        assert_eq!(texture.format, expected_format);
+        assert_eq!(texture.texture.mip_level_count(), 4);
 
-        Ok(Self {texture, view, sampler})
+        let linear = to_linear(&img, ColorSpace::Srgb);
+        let levels = std::iter::once(linear.clone()).chain(mipmap::cpu_mip_chain_linear(&linear));
+        for (level, expected) in levels.enumerate() {
+            let actual = decode_16(texture.format, &testing::read_bytes(&device, &queue, &texture.texture, level as u32));
+            assert_eq!(actual.len(), expected.as_raw().len(), "level {}", level);
+            // Half floats and the GPU's rounding both leave a little error
+            let difference = actual.iter().zip(expected.as_raw()).map(|(a, b)| (a - b).abs()).fold(0.0, f32::max);
+            assert!(difference < 0.01, "{:?} level {} is off by {}", texture.format, level, difference);
+        }
     }
 }
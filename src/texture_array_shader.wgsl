@@ -0,0 +1,37 @@
+// Vertex
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) tex_coords: vec2<f32>,
+    // From a per-vertex or per-instance buffer
+    @location(2) layer: u32,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) tex_coords: vec2<f32>,
+    // Backends disagree on which vertex of a triangle provides flat values,
+    // so all three should agree
+    @location(1) @interpolate(flat) layer: u32,
+};
+
+@vertex
+fn vs_main(
+    model: VertexInput,
+) -> VertexOutput {
+    var out: VertexOutput;
+    out.tex_coords = model.tex_coords;
+    out.layer = model.layer;
+    out.clip_position = camera.view_proj * model_transform.model * vec4<f32>(model.position, 1.0);
+    return out;
+}
+
+// Fragment
+@group(0) @binding(0)
+var t_diffuse: texture_2d_array<f32>;
+@group(0) @binding(1)
+var s_diffuse: sampler;
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return textureSample(t_diffuse, s_diffuse, in.tex_coords, in.layer);
+}
@@ -0,0 +1,294 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::mpsc,
+};
+
+use anyhow::*;
+
+use crate::{
+    procedural::{self, Pattern},
+    sampler::SamplerCache,
+    texture::{DecodedImage, Texture, TextureOptions},
+};
+
+// Magenta and black squares, obviously not a real texture
+const PLACEHOLDER_SIZE: u32 = 64;
+const PLACEHOLDER: Pattern = Pattern::Checker {
+    cells: 8,
+    colors: [[255, 0, 255, 255], [0, 0, 0, 255]],
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TextureHandle(usize);
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Source {
+    File(PathBuf),
+    // One layer per file
+    Array(Vec<PathBuf>),
+    // Decoded but kept on the CPU, for images packed into an atlas
+    Image(PathBuf),
+}
+
+impl Source {
+    fn paths(&self) -> &[PathBuf] {
+        match self {
+            Source::File(path) | Source::Image(path) => std::slice::from_ref(path),
+            Source::Array(paths) => paths,
+        }
+    }
+
+    fn label(&self) -> String {
+        match self {
+            Source::File(path) | Source::Image(path) => path.display().to_string(),
+            Source::Array(paths) => format!("array of {}", paths.iter().map(|path| path.display().to_string()).collect::<Vec<_>>().join(", ")),
+        }
+    }
+}
+
+// What `get` hands out until a texture has loaded, for anything else that
+// needs to show the same
+pub fn placeholder_image() -> image::RgbaImage {
+    procedural::generate_cpu(&PLACEHOLDER, PLACEHOLDER_SIZE, PLACEHOLDER_SIZE)
+}
+
+struct Request {
+    handle: TextureHandle,
+    source: Source,
+    options: TextureOptions,
+}
+
+struct Response {
+    handle: TextureHandle,
+    // One image per path in the source
+    result: Result<Vec<DecodedImage>>,
+}
+
+enum Slot {
+    Loading,
+    Ready(Texture),
+    Decoded(image::RgbaImage),
+    Failed,
+}
+
+struct Entry {
+    source: Source,
+    options: TextureOptions,
+    slot: Slot,
+}
+
+// Loads textures from disk by path. Files are read and decoded on a worker
+// thread, then uploaded on the render thread by `poll`. Until then, or if
+// loading fails, `get` hands out a placeholder. Loading the same path with
+// the same options again returns the existing handle
+pub struct TextureManager {
+    placeholder: Texture,
+    array_placeholder: Texture,
+    entries: Vec<Entry>,
+    handles: HashMap<(Source, TextureOptions), TextureHandle>,
+    requests: mpsc::Sender<Request>,
+    responses: mpsc::Receiver<Response>,
+    // Lets `load` report a failure to queue a request the same way the
+    // worker reports one
+    failures: mpsc::Sender<Response>,
+}
+
+impl TextureManager {
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue, samplers: &SamplerCache) -> Self {
+        let (requests, request_receiver) = mpsc::channel::<Request>();
+        let (response_sender, responses) = mpsc::channel();
+        let failures = response_sender.clone();
+        let work = move || {
+            for request in request_receiver {
+                let result = request.source.paths().iter().map(|path| decode_file(path, &request.options)).collect();
+                if response_sender.send(Response { handle: request.handle, result }).is_err() {
+                    break;
+                }
+            }
+        };
+        // The web build has no threads, or files to read, so its requests
+        // fail as soon as they are made and keep the placeholder
+        #[cfg(not(target_arch = "wasm32"))]
+        std::thread::Builder::new()
+            .name("texture_loader".into())
+            .spawn(work)
+            .expect("Couldn't start the texture loader thread");
+        #[cfg(target_arch = "wasm32")]
+        drop(work);
+
+        // Nearest filtering keeps the squares sharp however it's scaled
+        let placeholder_options = TextureOptions::default()
+            .with_filters(wgpu::FilterMode::Nearest, wgpu::FilterMode::Nearest, wgpu::FilterMode::Nearest);
+        let placeholder_image = image::DynamicImage::ImageRgba8(placeholder_image());
+        Self {
+            placeholder: Texture::from_pattern(device, queue, &PLACEHOLDER, (PLACEHOLDER_SIZE, PLACEHOLDER_SIZE), Some("placeholder_texture"), &placeholder_options, samplers)
+                .expect("the placeholder is a valid size"),
+            array_placeholder: Texture::array_from_images(device, queue, &[placeholder_image], Some("array_placeholder_texture"), &placeholder_options, samplers)
+                .expect("the placeholder is a plain RGBA8 image"),
+            entries: Vec::new(),
+            handles: HashMap::new(),
+            requests,
+            responses,
+            failures,
+        }
+    }
+
+    #[allow(unused)]
+    pub fn load(&mut self, path: impl AsRef<Path>, options: TextureOptions) -> TextureHandle {
+        self.request(Source::File(resolve(path.as_ref())), options)
+    }
+
+    // A D2Array texture with one layer per file, in order. The files must
+    // decode to images of the same size; compressed files aren't supported
+    pub fn load_array(&mut self, paths: &[impl AsRef<Path>], options: TextureOptions) -> TextureHandle {
+        self.request(Source::Array(paths.iter().map(|path| resolve(path.as_ref())).collect()), options)
+    }
+
+    // Only decodes the file, for `image` to hand out once `poll` reports it
+    pub fn load_image(&mut self, path: impl AsRef<Path>, options: TextureOptions) -> TextureHandle {
+        self.request(Source::Image(resolve(path.as_ref())), options)
+    }
+
+    fn request(&mut self, source: Source, options: TextureOptions) -> TextureHandle {
+        if let Some(handle) = self.handles.get(&(source.clone(), options)) {
+            return *handle;
+        }
+
+        let handle = TextureHandle(self.entries.len());
+        self.entries.push(Entry {
+            source: source.clone(),
+            options,
+            slot: Slot::Loading,
+        });
+        self.handles.insert((source.clone(), options), handle);
+        if self.requests.send(Request { handle, source, options }).is_err() {
+            let result = Err(anyhow!("Texture loader isn't running"));
+            self.failures.send(Response { handle, result }).unwrap();
+        }
+        handle
+    }
+
+    // Loads every texture made from `path` again. The current texture stays
+    // in use until the new one is uploaded
+    #[cfg(feature = "hot-reload")]
+    pub fn reload(&mut self, path: &Path) {
+        for (index, entry) in self.entries.iter().enumerate() {
+            if !entry.source.paths().iter().any(|source| source == path) {
+                continue;
+            }
+            let handle = TextureHandle(index);
+            let request = Request { handle, source: entry.source.clone(), options: entry.options };
+            if self.requests.send(request).is_err() {
+                let result = Err(anyhow!("Texture loader isn't running"));
+                self.failures.send(Response { handle, result }).unwrap();
+            }
+        }
+    }
+
+    // Every directory a texture has been requested from, for watching
+    #[cfg(feature = "hot-reload")]
+    pub fn directories(&self) -> Vec<PathBuf> {
+        let mut directories: Vec<PathBuf> = self.entries
+            .iter()
+            .flat_map(|entry| entry.source.paths())
+            .filter_map(|path| path.parent().map(Path::to_path_buf))
+            .collect();
+        directories.sort();
+        directories.dedup();
+        directories
+    }
+
+    // Uploads whatever the worker has finished since the last call and
+    // returns those handles, so bind groups using them can be recreated.
+    // Failed loads are returned with their error and keep the placeholder,
+    // or whatever texture they had before
+    pub fn poll(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, samplers: &SamplerCache) -> Vec<(TextureHandle, Result<()>)> {
+        let mut finished = Vec::new();
+        for Response { handle, result } in self.responses.try_iter() {
+            let entry = &mut self.entries[handle.0];
+            let label = entry.source.label();
+            let slot = result.and_then(|decoded| {
+                upload(device, queue, &entry.source, decoded, &label, &entry.options, samplers)
+                    .with_context(|| format!("Couldn't upload {}", label))
+            });
+            match slot {
+                Result::Ok(slot) => {
+                    entry.slot = slot;
+                    finished.push((handle, Ok(())));
+                },
+                Err(e) => {
+                    if !matches!(entry.slot, Slot::Ready(_) | Slot::Decoded(_)) {
+                        entry.slot = Slot::Failed;
+                    }
+                    finished.push((handle, Err(e)));
+                },
+            }
+        }
+        finished
+    }
+
+    // Array loads get an array placeholder, so it can be bound the same
+    // way as the finished texture
+    pub fn get(&self, handle: TextureHandle) -> &Texture {
+        let entry = &self.entries[handle.0];
+        match (&entry.slot, &entry.source) {
+            (Slot::Ready(texture), _) => texture,
+            (_, Source::Array(_)) => &self.array_placeholder,
+            (_, Source::File(_) | Source::Image(_)) => &self.placeholder,
+        }
+    }
+
+    // The decoded image of a `load_image`, once it's there
+    pub fn image(&self, handle: TextureHandle) -> Option<&image::RgbaImage> {
+        match &self.entries[handle.0].slot {
+            Slot::Decoded(image) => Some(image),
+            _ => None,
+        }
+    }
+
+    #[allow(unused)]
+    pub fn is_loading(&self, handle: TextureHandle) -> bool {
+        matches!(self.entries[handle.0].slot, Slot::Loading)
+    }
+}
+
+// Different spellings of the same file share a texture, as long as it exists
+// to be resolved
+fn resolve(path: &Path) -> PathBuf {
+    path.canonicalize().unwrap_or_else(|_| path.to_path_buf())
+}
+
+fn decode_file(path: &Path, options: &TextureOptions) -> Result<DecodedImage> {
+    let bytes = std::fs::read(path).with_context(|| format!("Couldn't read {}", path.display()))?;
+    DecodedImage::decode(&bytes, options).with_context(|| format!("Couldn't decode {}", path.display()))
+}
+
+fn upload(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    source: &Source,
+    decoded: Vec<DecodedImage>,
+    label: &str,
+    options: &TextureOptions,
+    samplers: &SamplerCache,
+) -> Result<Slot> {
+    match source {
+        Source::File(_) => Texture::from_decoded(device, queue, &decoded[0], Some(label), options, samplers).map(Slot::Ready),
+        Source::Image(path) => match decoded.into_iter().next() {
+            Some(DecodedImage::Image(img)) => Ok(Slot::Decoded(img.to_rgba8())),
+            _ => bail!("{} is compressed, which can't be packed into an atlas", path.display()),
+        },
+        Source::Array(paths) => {
+            let images = decoded
+                .into_iter()
+                .zip(paths)
+                .map(|(decoded, path)| match decoded {
+                    DecodedImage::Image(img) => Ok(img),
+                    DecodedImage::Compressed(_) => bail!("{} is compressed, which array layers can't be yet", path.display()),
+                })
+                .collect::<Result<Vec<_>>>()?;
+            Texture::array_from_images(device, queue, &images, Some(label), options, samplers).map(Slot::Ready)
+        },
+    }
+}
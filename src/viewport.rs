@@ -0,0 +1,197 @@
+use winit::dpi::{PhysicalPosition, PhysicalSize};
+
+use crate::camera::{Camera, OrthographicSize, Projection};
+
+// Fractions of the surface with (0, 0) at the top left
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ViewportRect {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+impl ViewportRect {
+    pub const FULL: Self = Self { x: 0.0, y: 0.0, width: 1.0, height: 1.0 };
+
+    fn quadrant(column: u32, row: u32) -> Self {
+        Self {
+            x: column as f32 * 0.5,
+            y: row as f32 * 0.5,
+            width: 0.5,
+            height: 0.5,
+        }
+    }
+}
+
+// Pixel rectangle inside the surface, as used by `set_viewport`/`set_scissor_rect`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PixelRect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl PixelRect {
+    pub fn size(&self) -> PhysicalSize<u32> {
+        PhysicalSize::new(self.width, self.height)
+    }
+
+    pub fn contains(&self, position: PhysicalPosition<f64>) -> bool {
+        position.x >= self.x as f64
+            && position.y >= self.y as f64
+            && position.x < (self.x + self.width) as f64
+            && position.y < (self.y + self.height) as f64
+    }
+
+    pub fn local_position(&self, position: PhysicalPosition<f64>) -> PhysicalPosition<f64> {
+        PhysicalPosition::new(position.x - self.x as f64, position.y - self.y as f64)
+    }
+}
+
+pub struct Viewport {
+    pub name: &'static str,
+    pub rect: ViewportRect,
+    pub camera: Camera,
+}
+
+impl Viewport {
+    pub fn pixel_rect(&self, surface: PhysicalSize<u32>) -> PixelRect {
+        let x = (self.rect.x * surface.width as f32).round() as u32;
+        let y = (self.rect.y * surface.height as f32).round() as u32;
+        let right = ((self.rect.x + self.rect.width) * surface.width as f32).round() as u32;
+        let bottom = ((self.rect.y + self.rect.height) * surface.height as f32).round() as u32;
+        PixelRect {
+            x,
+            y,
+            width: right.saturating_sub(x).max(1),
+            height: bottom.saturating_sub(y).max(1),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ViewportLayout {
+    Single,
+    // top, front and side orthographic views next to the perspective view
+    Quad,
+}
+
+impl ViewportLayout {
+    pub fn next(&self) -> Self {
+        match self {
+            ViewportLayout::Single => ViewportLayout::Quad,
+            ViewportLayout::Quad => ViewportLayout::Single,
+        }
+    }
+}
+
+pub const MAX_VIEWPORTS: usize = 4;
+const ORTHO_DISTANCE: f32 = 5.0;
+const ORTHO_HEIGHT: f32 = 3.0;
+
+// The first viewport is always the perspective one and the only one shown in
+// the single layout
+pub struct Viewports {
+    layout: ViewportLayout,
+    viewports: Vec<Viewport>,
+    active: usize,
+}
+
+impl Viewports {
+    pub fn new(camera: Camera) -> Self {
+        let ortho = |name, offset: cgmath::Vector3<f32>, up| Viewport {
+            name,
+            rect: ViewportRect::FULL,
+            camera: Camera {
+                eye: camera.target + offset * ORTHO_DISTANCE,
+                up,
+                projection: Projection::Orthographic(OrthographicSize::Height(ORTHO_HEIGHT)),
+                ..camera
+            },
+        };
+
+        let mut viewports = Self {
+            layout: ViewportLayout::Single,
+            viewports: vec![
+                Viewport { name: "perspective", rect: ViewportRect::FULL, camera },
+                ortho("top", cgmath::Vector3::unit_y(), -cgmath::Vector3::unit_z()),
+                ortho("front", cgmath::Vector3::unit_z(), cgmath::Vector3::unit_y()),
+                ortho("side", cgmath::Vector3::unit_x(), cgmath::Vector3::unit_y()),
+            ],
+            active: 0,
+        };
+        viewports.set_layout(ViewportLayout::Single);
+        viewports
+    }
+
+    pub fn layout(&self) -> ViewportLayout {
+        self.layout
+    }
+
+    pub fn set_layout(&mut self, layout: ViewportLayout) {
+        self.layout = layout;
+        match layout {
+            ViewportLayout::Single => {
+                self.viewports[0].rect = ViewportRect::FULL;
+                self.active = 0;
+            },
+            ViewportLayout::Quad => {
+                self.viewports[1].rect = ViewportRect::quadrant(0, 0);
+                self.viewports[2].rect = ViewportRect::quadrant(1, 0);
+                self.viewports[3].rect = ViewportRect::quadrant(0, 1);
+                self.viewports[0].rect = ViewportRect::quadrant(1, 1);
+            },
+        }
+    }
+
+    pub fn visible(&self) -> &[Viewport] {
+        match self.layout {
+            ViewportLayout::Single => &self.viewports[..1],
+            ViewportLayout::Quad => &self.viewports,
+        }
+    }
+
+    pub fn get(&self, index: usize) -> &Viewport {
+        &self.viewports[index]
+    }
+
+    pub fn get_mut(&mut self, index: usize) -> &mut Viewport {
+        &mut self.viewports[index]
+    }
+
+    pub fn active_index(&self) -> usize {
+        self.active
+    }
+
+    pub fn active(&self) -> &Viewport {
+        &self.viewports[self.active]
+    }
+
+    pub fn active_mut(&mut self) -> &mut Viewport {
+        &mut self.viewports[self.active]
+    }
+
+    // Makes the viewport under `position` active, returning true if that
+    // changed which one it is
+    pub fn activate_at(&mut self, position: PhysicalPosition<f64>, surface: PhysicalSize<u32>) -> bool {
+        let hovered = self.visible()
+            .iter()
+            .position(|viewport| viewport.pixel_rect(surface).contains(position));
+        match hovered {
+            Some(index) if index != self.active => {
+                self.active = index;
+                true
+            },
+            _ => false,
+        }
+    }
+
+    pub fn resize(&mut self, surface: PhysicalSize<u32>) {
+        for viewport in &mut self.viewports {
+            let rect = viewport.pixel_rect(surface);
+            viewport.camera.aspect = rect.width as f32 / rect.height as f32;
+        }
+    }
+}